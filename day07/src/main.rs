@@ -1,48 +1,286 @@
-use std::fs;
+use std::fmt::Write as _;
+use std::{env, fs};
+
+/// Fuel spent by a single crab for moving a given distance.
+/// Implementations must be non-decreasing and convex in `distance`,
+/// which makes the summed cost over all crabs convex in the target position.
+trait CostFunction {
+    fn cost(&self, distance: i64) -> i64;
+
+    fn total(&self, positions: &[i64], target: i64) -> i64 {
+        positions
+            .iter()
+            .fold(0, |a, crab| a + self.cost((target - crab).abs()))
+    }
+
+    /// Finds the cheapest target position by ternary search over the integers
+    /// between the leftmost and rightmost crab.
+    fn optimum(&self, positions: &[i64]) -> Alignment {
+        let mut lo = *positions.iter().min().unwrap();
+        let mut hi = *positions.iter().max().unwrap();
+        while hi - lo > 2 {
+            let m1 = lo + (hi - lo) / 3;
+            let m2 = hi - (hi - lo) / 3;
+            let (c1, c2) = (self.total(positions, m1), self.total(positions, m2));
+            if c1 < c2 {
+                hi = m2 - 1;
+            } else if c1 > c2 {
+                lo = m1 + 1;
+            } else {
+                lo = m1;
+                hi = m2;
+            }
+        }
+        best_of(self, positions, lo..=hi)
+    }
+
+    /// Total fuel for every position from the leftmost to the rightmost crab.
+    /// The generic version evaluates each point directly in O(n * range).
+    fn curve(&self, sums: &CrabSums) -> Vec<i64> {
+        sums.range()
+            .map(|p| self.total(&sums.positions, p))
+            .collect()
+    }
+}
+
+/// Prefix sums of crab counts and positions over `min..=max`, so the linear
+/// and quadratic parts of the total cost can be read off in O(1) per position.
+struct CrabSums {
+    positions: Vec<i64>,
+    min: i64,
+    max: i64,
+    /// `count[i]` is the number of crabs at or left of `min + i`.
+    count: Vec<i64>,
+    /// `sum[i]` is the sum of the positions of those crabs.
+    sum: Vec<i64>,
+    /// Sum of the squared positions of all crabs.
+    squares: i64,
+}
+
+impl CrabSums {
+    fn new(positions: &[i64]) -> Self {
+        let min = *positions.iter().min().unwrap();
+        let max = *positions.iter().max().unwrap();
+        let len = (max - min + 1) as usize;
+        let mut count = vec![0; len];
+        let mut sum = vec![0; len];
+        for &p in positions {
+            count[(p - min) as usize] += 1;
+            sum[(p - min) as usize] += p;
+        }
+        for i in 1..len {
+            count[i] += count[i - 1];
+            sum[i] += sum[i - 1];
+        }
+        CrabSums {
+            positions: positions.to_vec(),
+            min,
+            max,
+            count,
+            sum,
+            squares: positions.iter().map(|c| c * c).sum(),
+        }
+    }
+
+    fn range(&self) -> std::ops::RangeInclusive<i64> {
+        self.min..=self.max
+    }
+
+    /// Sum of `|p - crab|` over all crabs.
+    fn distances(&self, p: i64) -> i64 {
+        let i = (p - self.min) as usize;
+        let (left_count, left_sum) = (self.count[i], self.sum[i]);
+        let right_count = *self.count.last().unwrap() - left_count;
+        let right_sum = *self.sum.last().unwrap() - left_sum;
+        left_count * p - left_sum + right_sum - right_count * p
+    }
+
+    /// Sum of `(p - crab)^2` over all crabs.
+    fn squared_distances(&self, p: i64) -> i64 {
+        let n = self.positions.len() as i64;
+        let sum = *self.sum.last().unwrap();
+        n * p * p - 2 * p * sum + self.squares
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Alignment {
+    position: i64,
+    cost: i64,
+}
+
+/// Evaluates every candidate and keeps the leftmost cheapest one.
+fn best_of<C: CostFunction + ?Sized>(
+    cost_fn: &C,
+    positions: &[i64],
+    candidates: impl Iterator<Item = i64>,
+) -> Alignment {
+    candidates
+        .map(|position| Alignment {
+            position,
+            cost: cost_fn.total(positions, position),
+        })
+        .min_by_key(|a| (a.cost, a.position))
+        .unwrap()
+}
+
+/// One unit of fuel per step (part 1).
+struct Linear;
+
+impl CostFunction for Linear {
+    fn cost(&self, distance: i64) -> i64 {
+        distance
+    }
+
+    /// The sum of absolute distances is minimised by the median.
+    fn optimum(&self, positions: &[i64]) -> Alignment {
+        let mut sorted = positions.to_vec();
+        sorted.sort_unstable();
+        let median = sorted[(sorted.len() - 1) / 2];
+        best_of(self, positions, std::iter::once(median))
+    }
+
+    fn curve(&self, sums: &CrabSums) -> Vec<i64> {
+        sums.range().map(|p| sums.distances(p)).collect()
+    }
+}
+
+/// Each step costs one more than the previous one (part 2).
+struct Triangular;
+
+impl CostFunction for Triangular {
+    fn cost(&self, distance: i64) -> i64 {
+        distance * (distance + 1) / 2
+    }
+
+    /// The optimum lies within half a step of the mean, so checking the
+    /// integers around it is enough.
+    fn optimum(&self, positions: &[i64]) -> Alignment {
+        let n = positions.len() as i64;
+        let sum: i64 = positions.iter().sum();
+        let floor = sum.div_euclid(n);
+        let min = *positions.iter().min().unwrap();
+        let max = *positions.iter().max().unwrap();
+        best_of(
+            self,
+            positions,
+            (floor - 1..=floor + 2).map(|p| p.clamp(min, max)),
+        )
+    }
+
+    fn curve(&self, sums: &CrabSums) -> Vec<i64> {
+        sums.range()
+            .map(|p| (sums.squared_distances(p) + sums.distances(p)) / 2)
+            .collect()
+    }
+}
+
+/// Each step costs the distance squared.
+struct Squared;
+
+impl CostFunction for Squared {
+    fn cost(&self, distance: i64) -> i64 {
+        distance * distance
+    }
+
+    fn curve(&self, sums: &CrabSums) -> Vec<i64> {
+        sums.range().map(|p| sums.squared_distances(p)).collect()
+    }
+}
+
+/// Any other convex per-crab cost, solved by the generic ternary search.
+/// Its curve falls back to the O(n * range) default.
+struct Convex<F: Fn(i64) -> i64>(F);
+
+impl<F: Fn(i64) -> i64> CostFunction for Convex<F> {
+    fn cost(&self, distance: i64) -> i64 {
+        (self.0)(distance)
+    }
+}
+
+/// Renders the fuel curve of each named cost function as CSV, one row per
+/// candidate position, with the cheapest position flagged.
+fn curve_csv(sums: &CrabSums, cost_fns: &[(&str, &dyn CostFunction)]) -> String {
+    let mut csv = String::from("cost_function,position,fuel,optimal\n");
+    for (name, cost_fn) in cost_fns {
+        let optimum = cost_fn.optimum(&sums.positions);
+        for (position, fuel) in sums.range().zip(cost_fn.curve(sums)) {
+            let optimal = u8::from(position == optimum.position);
+            writeln!(csv, "{name},{position},{fuel},{optimal}").unwrap();
+        }
+    }
+    csv
+}
 
 fn main() {
     let contents = fs::read_to_string("day07/input").expect("could not read input");
-    let mut min = i32::MAX;
-    let mut max = i32::MIN;
-    let start_posiitons: Vec<_> = contents
+    let start_positions: Vec<i64> = contents
         .lines()
         .next()
         .unwrap()
         .split(',')
-        .map(|s| {
-            let v = s.parse::<i32>().unwrap();
-            min = min.min(v);
-            max = max.max(v);
-            v
-        })
+        .map(|s| s.parse::<i64>().unwrap())
         .collect();
-//    println!("input positions {:?}", start_posiitons);
+//    println!("input positions {:?}", start_positions);
 
-    let cost_fn = |position: i32| {
-        start_posiitons.iter().fold(0, |a, crab| {
-            let dist = (position - crab).abs();
-            a + (dist * (dist + 1)) / 2
-        })
-    };
+    let linear = Linear.optimum(&start_positions);
+    println!("linear: position {} cost {}", linear.position, linear.cost);
+
+    let triangular = Triangular.optimum(&start_positions);
+    println!(
+        "triangular: position {} cost {}",
+        triangular.position, triangular.cost
+    );
+
+    let squared = Squared.optimum(&start_positions);
+    println!(
+        "squared: position {} cost {}",
+        squared.position, squared.cost
+    );
 
-    let mut last_pos = min;
-    let mut last_cost = cost_fn(min);
-    let mut step = (min + max) / 2;
+    let cubic = Convex(|d| d * d * d).optimum(&start_positions);
+    println!("cubic: position {} cost {}", cubic.position, cubic.cost);
 
-    let mut next_pos = last_pos + step;
-    let mut next_cost = cost_fn(next_pos);
-    loop {
-        if next_cost > last_cost {
-            step = -step / 2;
+    if env::args().any(|a| a == "--export") {
+        let sums = CrabSums::new(&start_positions);
+        let csv = curve_csv(
+            &sums,
+            &[
+                ("linear", &Linear),
+                ("triangular", &Triangular),
+                ("squared", &Squared),
+            ],
+        );
+        fs::write("day07/fuel_curve.csv", csv).expect("could not write fuel curve");
+    }
+}
+
+#[test]
+fn curve_test() {
+    let positions = [16, 1, 2, 0, 4, 2, 7, 1, 2, 14];
+    let sums = CrabSums::new(&positions);
+    assert_eq!(
+        Linear.optimum(&positions),
+        Alignment {
+            position: 2,
+            cost: 37
         }
-        if step == 0 {
-            break;
+    );
+    assert_eq!(
+        Triangular.optimum(&positions),
+        Alignment {
+            position: 5,
+            cost: 168
         }
-        last_pos = next_pos;
-        last_cost = next_cost;
-        next_pos = last_pos + step;
-        next_cost = cost_fn(next_pos);
+    );
+    let generic: [&dyn CostFunction; 3] = [
+        &Convex(|d| d),
+        &Convex(|d| d * (d + 1) / 2),
+        &Convex(|d| d * d),
+    ];
+    let fast: [&dyn CostFunction; 3] = [&Linear, &Triangular, &Squared];
+    for (generic, fast) in generic.iter().zip(fast) {
+        assert_eq!(generic.curve(&sums), fast.curve(&sums));
+        assert_eq!(generic.optimum(&positions), fast.optimum(&positions));
     }
-
-    println!("position {last_pos} cost {last_cost}");
 }
@@ -8,6 +8,34 @@ fn to_bit_set(wires: &str) -> u8 {
         .fold(0u8, |a, c| a | 1 << ((c as usize) - ('a' as usize)))
 }
 
+fn to_wires(pattern: u8) -> Vec<char> {
+    (0..7)
+        .filter(|w| pattern & 1 << w != 0)
+        .map(|w| (b'a' + w) as char)
+        .collect()
+}
+
+/// Small xorshift generator, good enough to scramble test displays.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, (self.next() % (i as u64 + 1)) as usize);
+        }
+    }
+}
+
+/// The original decoder: needs 1, 7 and 4 among the patterns. Kept as a
+/// reference for `decode`.
+#[cfg(test)]
 struct PatternDef {
     one_cf: u8,
     four_bcdf: u8,
@@ -15,7 +43,18 @@ struct PatternDef {
     bd: u8,
 }
 
+#[cfg(test)]
 impl PatternDef {
+    /// Expects the patterns sorted by segment count, so that 1, 7 and 4 come first.
+    fn new(sorted_patterns: &[u8]) -> Self {
+        PatternDef {
+            one_cf: sorted_patterns[0],
+            seven_acf: sorted_patterns[1],
+            four_bcdf: sorted_patterns[2],
+            bd: sorted_patterns[2] & !sorted_patterns[0],
+        }
+    }
+
     fn discriminate(&self, pattern: u8) -> Option<u8> {
         let ones = pattern.count_ones();
         if pattern == self.one_cf {
@@ -49,6 +88,210 @@ impl PatternDef {
     }
 }
 
+/// Lit segments `a`..`g` of each digit on an unscrambled display.
+const DIGITS: [u8; 10] = [
+    0b1110111, // 0: abcefg
+    0b0100100, // 1: cf
+    0b1011101, // 2: acdeg
+    0b1101101, // 3: acdfg
+    0b0101110, // 4: bcdf
+    0b1101011, // 5: abdfg
+    0b1111011, // 6: abdefg
+    0b0100101, // 7: acf
+    0b1111111, // 8: abcdefg
+    0b1101111, // 9: abcdfg
+];
+
+fn digit_of(segments: u8) -> Option<u8> {
+    DIGITS.iter().position(|&d| d == segments).map(|d| d as u8)
+}
+
+/// A permutation of the seven wires: `segment[w]` is the display segment
+/// driven by wire `w`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Wiring {
+    segment: [u8; 7],
+}
+
+impl Wiring {
+    fn apply(&self, pattern: u8) -> u8 {
+        (0..7)
+            .filter(|w| pattern & 1 << w != 0)
+            .fold(0, |a, w| a | 1 << self.segment[w])
+    }
+
+    fn digit(&self, pattern: u8) -> Option<u8> {
+        digit_of(self.apply(pattern))
+    }
+
+    fn random(rng: &mut XorShift) -> Self {
+        let mut segment = [0, 1, 2, 3, 4, 5, 6];
+        rng.shuffle(&mut segment);
+        Wiring { segment }
+    }
+
+    /// Inverse of `apply`: the wires that light up the given segments.
+    fn scramble(&self, segments: u8) -> u8 {
+        (0..7)
+            .filter(|&w| segments & 1 << self.segment[w] != 0)
+            .fold(0, |a, w| a | 1 << w)
+    }
+
+    /// Renders all ten digits and the given output digits as a puzzle line.
+    /// With a generator, the patterns and the wires within each pattern are
+    /// listed in random order like in the real input.
+    fn encode(&self, digits: &[u8], mut rng: Option<&mut XorShift>) -> String {
+        let mut render = |digit: u8| {
+            let mut wires = to_wires(self.scramble(DIGITS[digit as usize]));
+            if let Some(rng) = rng.as_deref_mut() {
+                rng.shuffle(&mut wires);
+            }
+            wires.into_iter().collect::<String>()
+        };
+        let mut patterns: Vec<_> = (0..10).map(&mut render).collect();
+        let values: Vec<_> = digits.iter().map(|&d| render(d)).collect();
+        if let Some(rng) = rng {
+            rng.shuffle(&mut patterns);
+        }
+        format!("{} | {}", patterns.join(" "), values.join(" "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DecodeError {
+    /// The line is not `patterns | values` made of wires `a`..`g`.
+    Malformed(String),
+    /// The same pattern is listed more than once before the `|`.
+    Duplicate(u8),
+    /// A full set of ten patterns was given, but this value is not among them.
+    Missing { value: usize },
+    /// No wiring turns every pattern into a digit.
+    Inconsistent,
+    /// More than one wiring fits the observed patterns; two of them are given.
+    Ambiguous(Wiring, Wiring),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Decoded {
+    wiring: Wiring,
+    digits: Vec<u8>,
+}
+
+/// Recovers the wiring from whichever patterns are present, using both the
+/// listed patterns and the output values as constraints.
+fn decode(patterns: &[u8], values: &[u8]) -> Result<Decoded, DecodeError> {
+    for (i, pattern) in patterns.iter().enumerate() {
+        if patterns[..i].contains(pattern) {
+            return Err(DecodeError::Duplicate(*pattern));
+        }
+    }
+    if patterns.len() == DIGITS.len() {
+        if let Some(value) = values.iter().position(|v| !patterns.contains(v)) {
+            return Err(DecodeError::Missing { value });
+        }
+    }
+
+    let observed: Vec<u8> = patterns.iter().chain(values).copied().unique().collect();
+    let mut solutions = Vec::new();
+    let mut segment = [0u8; 7];
+    solve(&observed, &mut segment, 0, 0, &mut solutions);
+
+    match solutions.len() {
+        0 => Err(DecodeError::Inconsistent),
+        1 => {
+            let wiring = solutions[0];
+            let digits = values.iter().map(|&v| wiring.digit(v).unwrap()).collect();
+            Ok(Decoded { wiring, digits })
+        }
+        _ => Err(DecodeError::Ambiguous(solutions[0], solutions[1])),
+    }
+}
+
+/// Assigns wire `wire` to each unused segment in turn and backtracks as soon
+/// as some pattern can no longer become a digit. Stops after two solutions,
+/// which is enough to tell unique from ambiguous.
+fn solve(
+    observed: &[u8],
+    segment: &mut [u8; 7],
+    wire: usize,
+    used: u8,
+    solutions: &mut Vec<Wiring>,
+) {
+    if solutions.len() > 1 {
+        return;
+    }
+    if wire == 7 {
+        solutions.push(Wiring { segment: *segment });
+        return;
+    }
+    let assigned = (1u8 << (wire + 1)) - 1;
+    for s in (0..7).filter(|s| used & 1 << s == 0) {
+        segment[wire] = s;
+        let partial = Wiring { segment: *segment };
+        let feasible = observed.iter().all(|&pattern| {
+            let image = partial.apply(pattern & assigned);
+            let placed = used | 1 << s;
+            DIGITS
+                .iter()
+                .any(|&d| d.count_ones() == pattern.count_ones() && d & placed == image)
+        });
+        if feasible {
+            solve(observed, segment, wire + 1, used | 1 << s, solutions);
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Result<(Vec<u8>, Vec<u8>), DecodeError> {
+    let (patterns, values) = line
+        .split_once('|')
+        .ok_or_else(|| DecodeError::Malformed(line.to_string()))?;
+    let parse = |part: &str| -> Result<Vec<u8>, DecodeError> {
+        part.split_ascii_whitespace()
+            .map(|wires| {
+                if wires.chars().all(|c| ('a'..='g').contains(&c)) {
+                    Ok(to_bit_set(wires))
+                } else {
+                    Err(DecodeError::Malformed(wires.to_string()))
+                }
+            })
+            .collect()
+    };
+    Ok((parse(patterns)?, parse(values)?))
+}
+
+/// Encodes every digit under every one of the 5040 wirings and checks that
+/// both decoders recover the wiring and the digits from the generated line.
+#[test]
+fn round_trip_test() {
+    let mut rng = XorShift(0x2021_1208);
+    let digits: Vec<u8> = (0..10).collect();
+    for segment in (0..7).permutations(7) {
+        let wiring = Wiring {
+            segment: segment.try_into().unwrap(),
+        };
+        let line = wiring.encode(&digits, Some(&mut rng));
+        let (patterns, values) = parse_line(&line).unwrap();
+
+        let decoded = decode(&patterns, &values).unwrap();
+        assert_eq!(decoded.wiring, wiring, "wrong wiring for {line}");
+        assert_eq!(decoded.digits, digits, "wrong digits for {line}");
+
+        let sorted: Vec<_> = patterns
+            .into_iter()
+            .sorted_by_key(|p| p.count_ones())
+            .collect();
+        let def = PatternDef::new(&sorted);
+        let discriminated: Vec<_> = values.iter().map(|&v| def.discriminate(v)).collect();
+        assert!(
+            discriminated
+                .iter()
+                .copied()
+                .eq(digits.iter().map(|&d| Some(d))),
+            "PatternDef failed on {line}"
+        );
+    }
+}
+
 fn main() {
     let contents = fs::read_to_string("day08/input").expect("could not read input");
 
@@ -57,44 +300,34 @@ fn main() {
     let data: Vec<_> = contents
         .lines()
         .map(|s| {
-            let (patterns, values) = s.split_once('|').unwrap();
+            let (patterns, values) = parse_line(s).expect("malformed line");
+            let decoded = decode(&patterns, &values).expect("could not decode line");
             let patterns: Vec<_> = patterns
-                .split_ascii_whitespace()
-                .sorted_by(|a, b| a.len().cmp(&b.len()))
-                .map(to_bit_set)
-                .collect();
-
-            let def = PatternDef {
-                one_cf: patterns[0],
-                seven_acf: patterns[1],
-                four_bcdf: patterns[2],
-                bd: patterns[2] & !patterns[0],
-            };
-            let values: Vec<_> = values
-                .split_ascii_whitespace()
-                .map(|wires| {
-                    let pattern = to_bit_set(wires);
-                    def.discriminate(pattern)
-                })
+                .into_iter()
+                .sorted_by_key(|p| p.count_ones())
                 .collect();
-            (patterns, values)
+            (patterns, decoded)
         })
         .collect();
 
     let mut sum = 0u64;
-    for line in data {
-        line.1
+    for (patterns, decoded) in data {
+        decoded
+            .digits
             .iter()
-            .for_each(|digit| counts[digit.unwrap() as usize] += 1);
-        let value = line
-            .1
+            .for_each(|&digit| counts[digit as usize] += 1);
+        let value = decoded
+            .digits
             .iter()
-            .fold(0u64, |a, digit| a * 10 + (digit.unwrap() as u64));
+            .fold(0u64, |a, &digit| a * 10 + digit as u64);
         print!("patterns:");
-        for (i, pattern) in line.0.iter().enumerate() {
+        for (i, pattern) in patterns.iter().enumerate() {
             print!("{i}:{pattern:08b} (#{}), ", pattern.count_ones());
         }
-        println!(" values {:?} {}", line.1, value);
+        println!(
+            " values {:?} {} wiring {:?}",
+            decoded.digits, value, decoded.wiring.segment
+        );
         sum += value;
     }
     println!(
@@ -103,4 +336,54 @@ fn main() {
         counts[1] + counts[4] + counts[7] + counts[8],
         sum
     );
+
+    let mut rng = XorShift(0x2021_1208);
+    let sample = Wiring::random(&mut rng).encode(&[1, 2, 3, 4], Some(&mut rng));
+    println!("sample scrambled line: {sample}");
+}
+
+#[test]
+fn decode_error_test() {
+    let wires = |line: &str| parse_line(line).unwrap();
+    let digits = |wiring: Wiring, digits: &[u8]| -> Vec<u8> {
+        digits
+            .iter()
+            .map(|&d| wiring.scramble(DIGITS[d as usize]))
+            .collect()
+    };
+    let identity = Wiring {
+        segment: [0, 1, 2, 3, 4, 5, 6],
+    };
+
+    let (patterns, values) = wires("ab ab cdf | ab");
+    assert_eq!(
+        decode(&patterns, &values),
+        Err(DecodeError::Duplicate(patterns[0]))
+    );
+
+    let all = digits(identity, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    assert_eq!(
+        decode(&all, &[all[1], 0b11]),
+        Err(DecodeError::Missing { value: 1 })
+    );
+
+    // No digit lights exactly two segments other than 1, so two different
+    // two-wire patterns cannot both be digits.
+    let (patterns, values) = wires("ab cd | ab");
+    assert_eq!(decode(&patterns, &values), Err(DecodeError::Inconsistent));
+
+    // A lone 8 says nothing about the wiring.
+    let (patterns, values) = wires("abcdefg | abcdefg");
+    assert!(matches!(
+        decode(&patterns, &values),
+        Err(DecodeError::Ambiguous(a, b)) if a != b
+    ));
+
+    assert_eq!(
+        parse_line("abc dez | ab"),
+        Err(DecodeError::Malformed("dez".to_string()))
+    );
+    let decoded = decode(&all[..9], &digits(identity, &[9, 4])).unwrap();
+    assert_eq!(decoded.wiring, identity);
+    assert_eq!(decoded.digits, vec![9, 4]);
 }
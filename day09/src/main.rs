@@ -1,14 +1,14 @@
-use std::fs;
+use std::fmt::Write as _;
+use std::{env, fs};
+use termion::{color, style};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 struct Point {
     x: i32,
     y: i32,
 }
 trait Heightmap {
     fn get(&self, x: i32, y: i32) -> Option<i32>;
-    fn set(&mut self, x: i32, y: i32);
-    fn get_candidates(&mut self, stack: &mut Vec<Point>, x: i32, y: i32);
 }
 
 impl Heightmap for Vec<Vec<i32>> {
@@ -24,24 +24,249 @@ impl Heightmap for Vec<Vec<i32>> {
             }
         }
     }
+}
+
+#[derive(Clone, Debug)]
+struct Basin {
+    size: usize,
+    /// Lowest cell of the basin, the first one in reading order on ties.
+    low_point: Point,
+    /// Inclusive bounding box.
+    top_left: Point,
+    bottom_right: Point,
+}
+
+/// Every cell below 9 labelled with the id of the basin it drains into.
+/// Basins are the connected regions between walls of 9s, so plateaus without
+/// a strict low point are found as well.
+struct Basins {
+    width: usize,
+    labels: Vec<Option<usize>>,
+    basins: Vec<Basin>,
+}
+
+/// A row whose length differs from the first one.
+#[derive(Debug, PartialEq, Eq)]
+struct RaggedRow {
+    row: usize,
+    len: usize,
+    expected: usize,
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+impl Basins {
+    fn label(heightmap: &Vec<Vec<i32>>) -> Result<Self, RaggedRow> {
+        let width = heightmap.first().map_or(0, |row| row.len());
+        let height = heightmap.len();
+        if let Some((row, r)) = heightmap.iter().enumerate().find(|(_, r)| r.len() != width) {
+            return Err(RaggedRow {
+                row,
+                len: r.len(),
+                expected: width,
+            });
+        }
+        let in_basin = |x: usize, y: usize| heightmap.get(x as i32, y as i32).unwrap_or(9) < 9;
+
+        let mut parent: Vec<usize> = (0..width * height).collect();
+        for y in 0..height {
+            for x in 0..width {
+                if !in_basin(x, y) {
+                    continue;
+                }
+                for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+                    if in_basin(nx, ny) {
+                        let a = find(&mut parent, y * width + x);
+                        let b = find(&mut parent, ny * width + nx);
+                        parent[a] = b;
+                    }
+                }
+            }
+        }
+
+        let mut ids = vec![None; width * height];
+        let mut labels = vec![None; width * height];
+        let mut basins: Vec<Basin> = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if !in_basin(x, y) {
+                    continue;
+                }
+                let root = find(&mut parent, y * width + x);
+                let p = Point {
+                    x: x as i32,
+                    y: y as i32,
+                };
+                let id = *ids[root].get_or_insert_with(|| {
+                    basins.push(Basin {
+                        size: 0,
+                        low_point: p,
+                        top_left: p,
+                        bottom_right: p,
+                    });
+                    basins.len() - 1
+                });
+                labels[y * width + x] = Some(id);
+
+                let basin = &mut basins[id];
+                basin.size += 1;
+                let low = basin.low_point;
+                if heightmap[y][x] < heightmap[low.y as usize][low.x as usize] {
+                    basin.low_point = p;
+                }
+                basin.top_left.x = basin.top_left.x.min(p.x);
+                basin.top_left.y = basin.top_left.y.min(p.y);
+                basin.bottom_right.x = basin.bottom_right.x.max(p.x);
+                basin.bottom_right.y = basin.bottom_right.y.max(p.y);
+            }
+        }
+
+        Ok(Basins {
+            width,
+            labels,
+            basins,
+        })
+    }
+
+    fn id_of(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x as usize >= self.width {
+            return None;
+        }
+        *self.labels.get(y as usize * self.width + x as usize)?
+    }
+
+    fn basin_of(&self, x: i32, y: i32) -> Option<&Basin> {
+        self.id_of(x, y).map(|id| &self.basins[id])
+    }
+
+    /// Ids of the `n` biggest basins.
+    fn largest(&self, n: usize) -> Vec<usize> {
+        let mut ids: Vec<usize> = (0..self.basins.len()).collect();
+        ids.sort_by_key(|&id| std::cmp::Reverse(self.basins[id].size));
+        ids.truncate(n);
+        ids
+    }
+
+    /// Whether the cell belongs to one of `ids` and touches anything outside it.
+    fn on_outline(&self, x: i32, y: i32, ids: &[usize]) -> bool {
+        match self.id_of(x, y) {
+            Some(id) if ids.contains(&id) => [(0, -1), (0, 1), (-1, 0), (1, 0)]
+                .iter()
+                .any(|(dx, dy)| self.id_of(x + dx, y + dy) != Some(id)),
+            _ => false,
+        }
+    }
+}
+
+/// Spreads basin ids around the colour wheel by the golden angle so that
+/// neighbouring ids get clearly different hues.
+fn basin_colour(id: usize) -> (u8, u8, u8) {
+    let hue = (id as f64 * 137.507_764) % 360.0 / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let scale = |c: f64| (55.0 + c * 200.0) as u8;
+    (scale(r), scale(g), scale(b))
+}
 
-    fn set(&mut self, x: i32, y: i32) {
-        self[y as usize][x as usize] = 9;
+const WALL: (u8, u8, u8) = (40, 40, 40);
+const LOW_POINT: (u8, u8, u8) = (255, 255, 255);
+const OUTLINE: (u8, u8, u8) = (255, 0, 0);
+
+/// The colour of a cell in the PPM export: basins in their own colour, darker
+/// where the floor is higher, with low points and big basin outlines on top.
+fn cell_colour(
+    heightmap: &[Vec<i32>],
+    basins: &Basins,
+    outlined: &[usize],
+    p: Point,
+) -> (u8, u8, u8) {
+    match basins.id_of(p.x, p.y) {
+        None => WALL,
+        Some(id)
+            if basins.basins[id].low_point.x == p.x && basins.basins[id].low_point.y == p.y =>
+        {
+            LOW_POINT
+        }
+        Some(_) if basins.on_outline(p.x, p.y, outlined) => OUTLINE,
+        Some(id) => {
+            let (r, g, b) = basin_colour(id);
+            let shade =
+                |c: u8| (c as i32 * (12 - heightmap[p.y as usize][p.x as usize]) / 12) as u8;
+            (shade(r), shade(g), shade(b))
+        }
     }
+}
 
-    fn get_candidates(&mut self, stack: &mut Vec<Point>, x: i32, y: i32) {
-        if let Some(v) = self.get(x, y) {
-            if v < 9 {
-                self.set(x, y);
-                stack.push(Point { x, y });
+/// Prints the heights with every basin in its own colour. Low points are
+/// bold and inverted, the outline of the three largest basins is underlaid red.
+fn render_ansi(heightmap: &[Vec<i32>], basins: &Basins) -> String {
+    let outlined = basins.largest(3);
+    let mut out = String::new();
+    for (y, row) in heightmap.iter().enumerate() {
+        for (x, height) in row.iter().enumerate() {
+            let (x, y) = (x as i32, y as i32);
+            match basins.id_of(x, y) {
+                None => write!(
+                    out,
+                    "{}{height}",
+                    color::Fg(color::Rgb(WALL.0 * 2, WALL.1 * 2, WALL.2 * 2))
+                ),
+                Some(id) => {
+                    let (r, g, b) = basin_colour(id);
+                    write!(out, "{}", color::Fg(color::Rgb(r, g, b))).unwrap();
+                    let low = basins.basins[id].low_point;
+                    if low.x == x && low.y == y {
+                        write!(out, "{}{}", style::Bold, style::Invert).unwrap();
+                    }
+                    if basins.on_outline(x, y, &outlined) {
+                        let (r, g, b) = OUTLINE;
+                        write!(out, "{}", color::Bg(color::Rgb(r / 2, g / 2, b / 2))).unwrap();
+                    }
+                    write!(out, "{height}{}", style::Reset)
+                }
             }
+            .unwrap();
+        }
+        writeln!(out, "{}", style::Reset).unwrap();
+    }
+    out
+}
+
+/// Binary PPM image of the basin map with `scale` pixels per cell.
+fn render_ppm(heightmap: &[Vec<i32>], basins: &Basins, scale: usize) -> Vec<u8> {
+    let outlined = basins.largest(3);
+    let height = heightmap.len();
+    let width = heightmap.first().map_or(0, |row| row.len());
+    let mut ppm = format!("P6\n{} {}\n255\n", width * scale, height * scale).into_bytes();
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            let p = Point {
+                x: (x / scale) as i32,
+                y: (y / scale) as i32,
+            };
+            let (r, g, b) = cell_colour(heightmap, basins, &outlined, p);
+            ppm.extend([r, g, b]);
         }
     }
+    ppm
 }
 
 fn main() {
     let contents = fs::read_to_string("day09/input").expect("could not read input");
-    let mut heightmap: Vec<Vec<i32>> = contents
+    let heightmap: Vec<Vec<i32>> = contents
         .lines()
         .map(|l| l.chars().map(|c| c as i32 - '0' as i32).collect())
         .collect();
@@ -59,38 +284,21 @@ fn main() {
             if top > val && bot > val && left > val && right > val {
                 println!("low x {}, y {} {}", x, y, val);
                 score += val + 1;
-                seeds.push(Point {
-                    x: x as i32,
-                    y: y as i32,
-                });
+                seeds.push(Point { x, y });
             }
         }
     }
     println!("score {}", score);
 
-    let mut basin_scores = Vec::new();
-    for seed in seeds {
-        let mut basin_score = 0;
-        let mut stack = vec![seed];
-        loop {
-            let p = stack.pop();
-            match p {
-                Some(p) => {
-                    basin_score += 1;
-                    heightmap.set(p.x, p.y);
-
-                    heightmap.get_candidates(&mut stack, p.x, p.y - 1);
-                    heightmap.get_candidates(&mut stack, p.x, p.y + 1);
-                    heightmap.get_candidates(&mut stack, p.x - 1, p.y);
-                    heightmap.get_candidates(&mut stack, p.x + 1, p.y);
-                }
-                None => {
-                    basin_scores.push(basin_score);
-                    break;
-                }
-            }
-        }
+    let basins = Basins::label(&heightmap).expect("heightmap is not rectangular");
+    for seed in &seeds {
+        let basin = basins.basin_of(seed.x, seed.y).unwrap();
+        println!(
+            "basin of low {},{}: size {} low point {:?} bounds {:?}-{:?}",
+            seed.x, seed.y, basin.size, basin.low_point, basin.top_left, basin.bottom_right
+        );
     }
+    let mut basin_scores: Vec<u64> = basins.basins.iter().map(|b| b.size as u64).collect();
 
     basin_scores.sort_by(|a, b| b.cmp(a));
 
@@ -99,4 +307,70 @@ fn main() {
         basin_scores,
         basin_scores.iter().take(3).product::<u64>()
     );
+
+    print!("{}", render_ansi(&heightmap, &basins));
+    if env::args().any(|a| a == "--export") {
+        fs::write("day09/basins.ppm", render_ppm(&heightmap, &basins, 4))
+            .expect("could not write image");
+    }
+}
+
+#[cfg(test)]
+fn parse_heightmap(contents: &str) -> Vec<Vec<i32>> {
+    contents
+        .lines()
+        .map(|l| l.chars().map(|c| c as i32 - '0' as i32).collect())
+        .collect()
+}
+
+#[test]
+fn basins_test() {
+    let p = |x, y| Point { x, y };
+    let basins = Basins::label(&parse_heightmap(include_str!("../input_simple"))).unwrap();
+    let mut found: Vec<(usize, Point, Point, Point)> = basins
+        .basins
+        .iter()
+        .map(|b| (b.size, b.low_point, b.top_left, b.bottom_right))
+        .collect();
+    found.sort_by_key(|&(size, low, _, _)| (size, low.y, low.x));
+    assert_eq!(
+        found,
+        vec![
+            (3, p(1, 0), p(0, 0), p(1, 1)),
+            (9, p(9, 0), p(5, 0), p(9, 2)),
+            (9, p(6, 4), p(5, 2), p(9, 4)),
+            (14, p(2, 2), p(0, 1), p(5, 4)),
+        ]
+    );
+    let sizes: Vec<usize> = basins
+        .largest(3)
+        .iter()
+        .map(|&id| basins.basins[id].size)
+        .collect();
+    assert_eq!(sizes, vec![14, 9, 9]);
+    assert_eq!(basins.basin_of(0, 1).unwrap().low_point, p(1, 0));
+    assert_eq!(basins.basin_of(5, 4).unwrap().low_point, p(6, 4));
+    assert!(basins.basin_of(2, 0).is_none());
+    assert!(basins.basin_of(10, 0).is_none());
+
+    // A plateau has no strict low point, its first cell stands in for one.
+    let basins = Basins::label(&parse_heightmap("99999\n95559\n99959\n91999")).unwrap();
+    assert_eq!(basins.basins.len(), 2);
+    let plateau = basins.basin_of(3, 2).unwrap();
+    assert_eq!(plateau.size, 4);
+    assert_eq!(plateau.low_point, p(1, 1));
+    assert_eq!((plateau.top_left, plateau.bottom_right), (p(1, 1), p(3, 2)));
+    assert_eq!(basins.basin_of(1, 3).unwrap().size, 1);
+}
+
+#[test]
+fn ragged_test() {
+    assert_eq!(
+        Basins::label(&parse_heightmap("123\n45\n678")).err(),
+        Some(RaggedRow {
+            row: 1,
+            len: 2,
+            expected: 3,
+        })
+    );
 }
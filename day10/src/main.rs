@@ -1,84 +1,345 @@
-use core::panic;
-use std::{collections::HashMap, fs};
+use std::{collections::HashMap, env, fs};
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum ParseResult {
-    Illegal(char),
-    Incomplete(Vec<char>)
+    /// A closer that does not match the innermost open bracket.
+    Illegal {
+        column: usize,
+        found: char,
+        expected: char,
+    },
+    /// A closer while no bracket is open.
+    UnexpectedCloser { column: usize },
+    /// All closers matched; `completion` closes whatever is still open and is
+    /// empty for a complete line.
+    Incomplete { completion: String },
 }
 
-trait Parser {
-    fn parse_braces(&self) -> ParseResult;
+/// A bracket language given by its pairs of opening and closing characters.
+/// Characters that are not part of any pair are skipped. A pair may use the
+/// same character for both, like `|`: it closes when it is the closer
+/// expected next and opens otherwise.
+struct BracketSyntax {
+    pairs: Vec<(char, char)>,
 }
 
-impl Parser for str {
-    fn parse_braces(&self) -> ParseResult {
-        let mut stack: Vec<char> = Vec::new();
-        println!("parsing: {:?}", self);
-        for c in self.chars() {
-            match c {
-                '(' | '[' | '{' | '<' => {
-                    stack.push(c);
-                }
-                _ => {
-                    if let Some(opened) = stack.pop() {
-                        let expected = match opened {
-                            '(' => ')',
-                            '[' => ']',
-                            '{' => '}',
-                            '<' => '>',
-                            _ => panic!("unexpected stack content {}", opened),
-                        };
-                        if c != expected {
-                            println!("Expected {}, found {}", expected, c);
-                            return ParseResult::Illegal(c);
+impl BracketSyntax {
+    fn new(pairs: &[(char, char)]) -> Self {
+        BracketSyntax {
+            pairs: pairs.to_vec(),
+        }
+    }
+
+    fn standard() -> Self {
+        Self::new(&[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')])
+    }
+
+    fn closer_of(&self, opener: char) -> Option<char> {
+        self.pairs.iter().find(|p| p.0 == opener).map(|p| p.1)
+    }
+
+    fn is_closer(&self, c: char) -> bool {
+        self.pairs.iter().any(|p| p.1 == c)
+    }
+
+    /// Columns in the result are 1-based character positions.
+    fn parse(&self, line: &str) -> ParseResult {
+        let mut expected_closers: Vec<char> = Vec::new();
+        for (i, c) in line.chars().enumerate() {
+            let column = i + 1;
+            if expected_closers.last() == Some(&c) {
+                expected_closers.pop();
+            } else if let Some(closer) = self.closer_of(c) {
+                expected_closers.push(closer);
+            } else if self.is_closer(c) {
+                match expected_closers.pop() {
+                    Some(expected) if expected != c => {
+                        return ParseResult::Illegal {
+                            column,
+                            found: c,
+                            expected,
                         }
                     }
+                    Some(_) => {}
+                    None => return ParseResult::UnexpectedCloser { column },
                 }
             }
         }
-        println!("remaining stack {:?}", stack);
-        ParseResult::Incomplete(stack)
+        ParseResult::Incomplete {
+            completion: expected_closers.iter().rev().collect(),
+        }
+    }
+}
+
+/// What to do with a corrupted line when repairing a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CorruptAction {
+    Drop,
+    /// Cut the line before the offending closer and complete the rest. Lines
+    /// that would end up empty are dropped instead.
+    Truncate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Repair {
+    Unchanged,
+    Completed { completion: String },
+    Dropped { column: usize },
+    Truncated { column: usize, completion: String },
+}
+
+impl BracketSyntax {
+    /// Returns the repaired line, or `None` if it was dropped.
+    fn repair(&self, line: &str, action: CorruptAction) -> (Option<String>, Repair) {
+        let column = match self.parse(line) {
+            ParseResult::Incomplete { completion } if completion.is_empty() => {
+                return (Some(line.to_string()), Repair::Unchanged)
+            }
+            ParseResult::Incomplete { completion } => {
+                return (
+                    Some(format!("{line}{completion}")),
+                    Repair::Completed { completion },
+                )
+            }
+            ParseResult::Illegal { column, .. } | ParseResult::UnexpectedCloser { column } => {
+                column
+            }
+        };
+        match action {
+            CorruptAction::Truncate if column > 1 => {
+                let prefix: String = line.chars().take(column - 1).collect();
+                let completion = match self.parse(&prefix) {
+                    ParseResult::Incomplete { completion } => completion,
+                    _ => unreachable!("prefix before the first error parses cleanly"),
+                };
+                (
+                    Some(format!("{prefix}{completion}")),
+                    Repair::Truncated { column, completion },
+                )
+            }
+            _ => (None, Repair::Dropped { column }),
+        }
+    }
+
+    /// Repairs every line of `input`, returning the new contents and the
+    /// action taken for each line.
+    fn repair_all(&self, input: &str, action: CorruptAction) -> (String, Vec<Repair>) {
+        let mut output = String::new();
+        let mut repairs = Vec::new();
+        for line in input.lines() {
+            let (repaired, repair) = self.repair(line, action);
+            if let Some(repaired) = repaired {
+                output.push_str(&repaired);
+                output.push('\n');
+            }
+            repairs.push(repair);
+        }
+        (output, repairs)
+    }
+}
+
+/// `day10 repair <input> <output> [--truncate]` rewrites a file of
+/// navigation lines and reports what it changed on stderr.
+fn repair_file(args: &[String]) {
+    let (input, output) = match args {
+        [input, output, ..] => (input, output),
+        _ => panic!("usage: day10 repair <input> <output> [--truncate]"),
+    };
+    let action = if args[2..].iter().any(|a| a == "--truncate") {
+        CorruptAction::Truncate
+    } else {
+        CorruptAction::Drop
+    };
+    let contents = fs::read_to_string(input).expect("could not read input");
+    let (repaired, repairs) = BracketSyntax::standard().repair_all(&contents, action);
+    fs::write(output, repaired).expect("could not write output");
+
+    let mut totals: HashMap<&str, usize> = HashMap::new();
+    for (row, repair) in repairs.iter().enumerate() {
+        let kind = match repair {
+            Repair::Unchanged => "unchanged",
+            Repair::Completed { completion } => {
+                eprintln!("{input}:{}: appended {completion}", row + 1);
+                "completed"
+            }
+            Repair::Dropped { column } => {
+                eprintln!("{input}:{}:{column}: dropped corrupted line", row + 1);
+                "dropped"
+            }
+            Repair::Truncated { column, completion } => {
+                eprintln!(
+                    "{input}:{}:{column}: truncated and appended {completion}",
+                    row + 1
+                );
+                "truncated"
+            }
+        };
+        *totals.entry(kind).or_default() += 1;
+    }
+    eprintln!(
+        "{} lines: {} unchanged, {} completed, {} dropped, {} truncated",
+        repairs.len(),
+        totals.get("unchanged").unwrap_or(&0),
+        totals.get("completed").unwrap_or(&0),
+        totals.get("dropped").unwrap_or(&0),
+        totals.get("truncated").unwrap_or(&0),
+    );
+}
+
+/// Points per illegal closer, and per closer of a completion string which is
+/// scored as a number in base `completion_base`.
+struct Scoring {
+    illegal: HashMap<char, u64>,
+    completion: HashMap<char, u64>,
+    completion_base: u64,
+}
+
+impl Scoring {
+    fn standard() -> Self {
+        Scoring {
+            illegal: HashMap::from([(')', 3), (']', 57), ('}', 1197), ('>', 25137)]),
+            completion: HashMap::from([(')', 1), (']', 2), ('}', 3), ('>', 4)]),
+            completion_base: 5,
+        }
+    }
+
+    fn illegal_score(&self, found: char) -> u64 {
+        self.illegal.get(&found).copied().unwrap_or(0)
+    }
+
+    fn completion_score(&self, completion: &str) -> u64 {
+        completion.chars().fold(0, |a, c| {
+            a * self.completion_base + self.completion.get(&c).copied().unwrap_or(0)
+        })
     }
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("repair") {
+        repair_file(&args[1..]);
+        return;
+    }
+
     let contents = fs::read_to_string("day10/input").expect("could not read input");
     let input = contents.lines();
+    let syntax = BracketSyntax::standard();
+    let scoring = Scoring::standard();
     let mut completion_scores = Vec::new();
-    let mut scores: HashMap<char, i32> = HashMap::new();
-    for line in input {
-        match line.parse_braces() {
-            ParseResult::Illegal(ch) => {
-                let e = scores.entry(ch).or_default();
+    let mut scores: HashMap<char, u64> = HashMap::new();
+    for (row, line) in input.enumerate() {
+        match syntax.parse(line) {
+            ParseResult::Illegal {
+                column,
+                found,
+                expected,
+            } => {
+                println!("{}:{column}: expected {expected}, found {found}", row + 1);
+                let e = scores.entry(found).or_default();
                 *e += 1;
             }
-            ParseResult::Incomplete(stack) => {
-                let completion_score = stack.iter().rev().fold(0u64, |a, c| {
-                    a * 5
-                        + match c {
-                            '(' => 1,
-                            '[' => 2,
-                            '{' => 3,
-                            '<' => 4,
-                            _ => panic!("unexpected stack value {}", c),
-                        }
-                });
-                println!("completion score {}", completion_score);
+            ParseResult::UnexpectedCloser { column } => {
+                println!("{}:{column}: unexpected closer", row + 1);
+            }
+            ParseResult::Incomplete { completion } => {
+                let completion_score = scoring.completion_score(&completion);
+                println!("completion {completion} score {completion_score}");
                 completion_scores.push(completion_score);
             }
         }
     }
-    let score = scores.iter().fold(0, |a, e| {
-        a + e.1
-            * match e.0 {
-                ')' => 3,
-                ']' => 57,
-                '}' => 1197,
-                '>' => 25137,
-                _ => 0,
-            }
-    });
+    let score: u64 = scores
+        .iter()
+        .map(|(&c, count)| count * scoring.illegal_score(c))
+        .sum();
 
     completion_scores.sort();
-    println!("scores: {:?} {} completion score {:?}", scores, score, completion_scores[completion_scores.len()/2]);
+    println!(
+        "scores: {:?} {} completion score {:?}",
+        scores,
+        score,
+        completion_scores[completion_scores.len() / 2]
+    );
+}
+
+#[test]
+fn symmetric_pair_test() {
+    let syntax = BracketSyntax::new(&[('(', ')'), ('|', '|')]);
+    let incomplete = |completion: &str| ParseResult::Incomplete {
+        completion: completion.to_string(),
+    };
+    assert_eq!(syntax.parse("|(||)|"), incomplete(""));
+    assert_eq!(syntax.parse("(|"), incomplete("|)"));
+    assert_eq!(
+        syntax.parse("(|)"),
+        ParseResult::Illegal {
+            column: 3,
+            found: ')',
+            expected: '|'
+        }
+    );
+    assert_eq!(
+        BracketSyntax::standard().parse("[<>({}){}[([])<>]]"),
+        incomplete("")
+    );
+}
+
+#[test]
+fn repair_test() {
+    let syntax = BracketSyntax::standard();
+    for (line, action, repaired, repair) in [
+        ("[<>]", CorruptAction::Drop, Some("[<>]"), Repair::Unchanged),
+        (
+            "[<",
+            CorruptAction::Drop,
+            Some("[<>]"),
+            Repair::Completed {
+                completion: ">]".to_string(),
+            },
+        ),
+        (
+            "[(>]",
+            CorruptAction::Drop,
+            None,
+            Repair::Dropped { column: 3 },
+        ),
+        (
+            "[(>]",
+            CorruptAction::Truncate,
+            Some("[()]"),
+            Repair::Truncated {
+                column: 3,
+                completion: ")]".to_string(),
+            },
+        ),
+        (
+            ")[]",
+            CorruptAction::Truncate,
+            None,
+            Repair::Dropped { column: 1 },
+        ),
+    ] {
+        assert_eq!(
+            syntax.repair(line, action),
+            (repaired.map(str::to_string), repair),
+            "repairing {line} with {action:?}"
+        );
+    }
+
+    let (output, repairs) = syntax.repair_all("()\n)(\n{[}\n<\n", CorruptAction::Truncate);
+    assert_eq!(output, "()\n{[]}\n<>\n");
+    assert_eq!(
+        repairs,
+        vec![
+            Repair::Unchanged,
+            Repair::Dropped { column: 1 },
+            Repair::Truncated {
+                column: 3,
+                completion: "]}".to_string(),
+            },
+            Repair::Completed {
+                completion: ">".to_string(),
+            },
+        ]
+    );
 }
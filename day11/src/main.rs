@@ -1,22 +1,99 @@
+use std::collections::HashMap;
 use std::fs;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 struct Point {
     x: usize,
     y: usize,
 }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Neighbourhood {
+    /// Orthogonal neighbours only.
+    Four,
+    /// Orthogonal and diagonal neighbours.
+    Eight,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Edges {
+    /// Cells on the border have fewer neighbours.
+    Clamped,
+    /// The grid wraps around like a torus.
+    Toroidal,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Rules {
+    /// An octopus flashes once its energy exceeds this level.
+    threshold: u32,
+    neighbourhood: Neighbourhood,
+    edges: Edges,
+}
+
+impl Rules {
+    fn standard() -> Self {
+        Rules {
+            threshold: 9,
+            neighbourhood: Neighbourhood::Eight,
+            edges: Edges::Clamped,
+        }
+    }
+
+    /// Distinct neighbours of `p`, never including `p` itself.
+    fn neighbours(&self, p: Point, width: usize, height: usize) -> Vec<Point> {
+        let mut neighbours = Vec::new();
+        for dy in -1i64..=1 {
+            for dx in -1i64..=1 {
+                if (dx, dy) == (0, 0)
+                    || (self.neighbourhood == Neighbourhood::Four && dx != 0 && dy != 0)
+                {
+                    continue;
+                }
+                let (x, y) = (p.x as i64 + dx, p.y as i64 + dy);
+                let (w, h) = (width as i64, height as i64);
+                let (x, y) = match self.edges {
+                    Edges::Clamped if x < 0 || y < 0 || x >= w || y >= h => continue,
+                    Edges::Clamped => (x, y),
+                    Edges::Toroidal => (x.rem_euclid(w), y.rem_euclid(h)),
+                };
+                let n = Point {
+                    x: x as usize,
+                    y: y as usize,
+                };
+                if n != p && !neighbours.contains(&n) {
+                    neighbours.push(n);
+                }
+            }
+        }
+        neighbours
+    }
+}
+
+/// One flash in the order it happened. Flashes without a trigger were
+/// charged by the age phase and ignite the cascade.
+#[derive(Copy, Clone, Debug)]
+struct Flash {
+    point: Point,
+    /// Index of the flash in the same trace that pushed this one over the threshold.
+    trigger: Option<usize>,
+    depth: usize,
+}
+
 trait FlashPropagator {
-    fn age_phase(&mut self, stack: &mut Vec<Point>);
-    fn flash_phase(&mut self, stack: Vec<Point>) -> usize;
+    fn age_phase(&mut self, rules: &Rules, stack: &mut Vec<Point>);
+    fn flash_phase(&mut self, rules: &Rules, stack: Vec<Point>) -> usize;
+    fn flash_trace(&mut self, rules: &Rules, stack: Vec<Point>) -> Vec<Flash>;
+    fn step(&mut self, rules: &Rules) -> usize;
     fn dump(&self);
 }
 
 impl FlashPropagator for Vec<Vec<u32>> {
-    fn age_phase(&mut self, stack: &mut Vec<Point>) {
+    fn age_phase(&mut self, rules: &Rules, stack: &mut Vec<Point>) {
         for (y, row) in self.iter_mut().enumerate() {
             for (x, fish) in row.iter_mut().enumerate() {
                 *fish += 1;
-                if *fish > 9 {
+                if *fish > rules.threshold {
                     stack.push(Point { x, y });
                     *fish = 0;
                 }
@@ -24,26 +101,46 @@ impl FlashPropagator for Vec<Vec<u32>> {
         }
     }
 
-    fn flash_phase(&mut self, stack: Vec<Point>) -> usize {
-        let mut stack = stack;
-        let mut counter = 0;
-        while let Some(p) = stack.pop() {
-            counter += 1;
-            for y in 0.max(p.y as i32 - 1) as usize..self.len().min(p.y + 2) {
-                let row = &mut self[y];
-                for x in 0.max(p.x as i32 - 1) as usize..row.len().min(p.x + 2) {
-                    if row[x] == 0 {
-                        continue;
-                    }
-                    row[x] += 1;
-                    if row[x] > 9 {
-                        row[x] = 0;
-                        stack.push(Point { x, y })
-                    }
+    fn flash_phase(&mut self, rules: &Rules, stack: Vec<Point>) -> usize {
+        self.flash_trace(rules, stack).len()
+    }
+
+    fn flash_trace(&mut self, rules: &Rules, stack: Vec<Point>) -> Vec<Flash> {
+        let mut trace: Vec<Flash> = stack
+            .into_iter()
+            .map(|point| Flash {
+                point,
+                trigger: None,
+                depth: 0,
+            })
+            .collect();
+        let mut stack: Vec<usize> = (0..trace.len()).collect();
+        let (width, height) = (self[0].len(), self.len());
+        while let Some(i) = stack.pop() {
+            for n in rules.neighbours(trace[i].point, width, height) {
+                let fish = &mut self[n.y][n.x];
+                if *fish == 0 {
+                    continue;
+                }
+                *fish += 1;
+                if *fish > rules.threshold {
+                    *fish = 0;
+                    stack.push(trace.len());
+                    trace.push(Flash {
+                        point: n,
+                        trigger: Some(i),
+                        depth: trace[i].depth + 1,
+                    });
                 }
             }
         }
-        counter
+        trace
+    }
+
+    fn step(&mut self, rules: &Rules) -> usize {
+        let mut stack = Vec::new();
+        self.age_phase(rules, &mut stack);
+        self.flash_phase(rules, stack)
     }
 
     fn dump(&self) {
@@ -53,32 +150,231 @@ impl FlashPropagator for Vec<Vec<u32>> {
     }
 }
 
+/// Simulates up to `step` (1-based) and traces the flashes of that step.
+fn trace_step(grid: &[Vec<u32>], rules: &Rules, step: usize) -> Vec<Flash> {
+    let mut grid = grid.to_vec();
+    for _ in 1..step {
+        grid.step(rules);
+    }
+    let mut stack = Vec::new();
+    grid.age_phase(rules, &mut stack);
+    grid.flash_trace(rules, stack)
+}
+
+/// Number of flashes set off by each ignition point, including itself.
+fn cascade_sizes(trace: &[Flash]) -> Vec<(Point, usize)> {
+    let mut root = vec![0; trace.len()];
+    let mut sizes: Vec<(Point, usize)> = Vec::new();
+    for (i, flash) in trace.iter().enumerate() {
+        root[i] = match flash.trigger {
+            Some(trigger) => root[trigger],
+            None => {
+                sizes.push((flash.point, 0));
+                sizes.len() - 1
+            }
+        };
+        sizes[root[i]].1 += 1;
+    }
+    sizes
+}
+
+/// The grid states from step `start` on repeat every `period` steps.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Cycle {
+    start: usize,
+    period: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Synchronisation {
+    /// Every octopus flashed during this step.
+    At(usize),
+    /// The simulation entered a cycle without every octopus flashing at once.
+    Never(Cycle),
+    /// Neither happened within the step limit.
+    Unknown { steps: usize },
+}
+
+/// Runs `grid` until `done` accepts the number of flashes in a step or a
+/// state repeats, whichever comes first.
+fn run_until(
+    grid: &[Vec<u32>],
+    rules: &Rules,
+    limit: usize,
+    done: impl Fn(usize) -> bool,
+) -> Synchronisation {
+    let mut grid = grid.to_vec();
+    let mut seen = HashMap::from([(grid.clone(), 0)]);
+    for step in 1..=limit {
+        if done(grid.step(rules)) {
+            return Synchronisation::At(step);
+        }
+        if let Some(start) = seen.insert(grid.clone(), step) {
+            return Synchronisation::Never(Cycle {
+                start,
+                period: step - start,
+            });
+        }
+    }
+    Synchronisation::Unknown { steps: limit }
+}
+
+/// Runs `grid` until a state repeats and returns where the repetition
+/// starts, or `None` if none shows up within `limit` steps.
+fn find_cycle(grid: &[Vec<u32>], rules: &Rules, limit: usize) -> Option<Cycle> {
+    match run_until(grid, rules, limit, |_| false) {
+        Synchronisation::Never(cycle) => Some(cycle),
+        _ => None,
+    }
+}
+
+/// Finds the first step in which every octopus flashes. Because the
+/// simulation is deterministic, a repeated state without such a step means
+/// it never happens.
+fn synchronise(grid: &[Vec<u32>], rules: &Rules, limit: usize) -> Synchronisation {
+    let size = grid.len() * grid[0].len();
+    run_until(grid, rules, limit, |flashes| flashes == size)
+}
+
 fn main() {
     let contents = fs::read_to_string("day11/input").expect("could not read input");
     let input: Vec<Vec<u32>> = contents
         .lines()
         .map(|l| l.chars().map(|c| c.to_digit(10).unwrap()).collect())
         .collect();
+    let rules = Rules::standard();
 
     let mut counter = 0usize;
     let mut workset = input.clone();
     for _cycle in 0..100 {
-        let mut stack = Vec::new();
-        workset.age_phase(&mut stack);
-        counter += workset.flash_phase(stack);
+        counter += workset.step(&rules);
     }
+    workset.dump();
     println!("flashes {}", counter);
 
-    let mut workset = input;
-    let mut cycle = 0;
-    loop {
-        let mut stack = Vec::new();
-        workset.age_phase(&mut stack);
-        let count = workset.flash_phase(stack);
-        cycle += 1;
-        println!("stepw {} flashes {}", cycle, count);
-        if count == workset.len() * workset[0].len() {
-            break;
-        }
+    let trace = trace_step(&input, &rules, 100);
+    for (i, flash) in trace.iter().enumerate() {
+        let trigger = match flash.trigger {
+            Some(t) => format!("#{t} at {},{}", trace[t].point.x, trace[t].point.y),
+            None => "ignition".to_string(),
+        };
+        println!(
+            "step 100 flash #{i} at {},{} depth {} from {trigger}",
+            flash.point.x, flash.point.y, flash.depth
+        );
+    }
+    for (point, size) in cascade_sizes(&trace) {
+        println!("ignition point {},{} cascade size {size}", point.x, point.y);
+    }
+    println!("step 100 cascade size {}", trace.len());
+
+    println!("synchronisation {:?}", synchronise(&input, &rules, 10_000));
+    println!("cycle {:?}", find_cycle(&input, &rules, 10_000));
+
+    for (neighbourhood, edges) in [
+        (Neighbourhood::Four, Edges::Clamped),
+        (Neighbourhood::Four, Edges::Toroidal),
+        (Neighbourhood::Eight, Edges::Toroidal),
+    ] {
+        let rules = Rules {
+            neighbourhood,
+            edges,
+            ..rules
+        };
+        println!(
+            "{:?} {:?}: synchronisation {:?}",
+            neighbourhood,
+            edges,
+            synchronise(&input, &rules, 10_000)
+        );
     }
 }
+
+#[cfg(test)]
+fn parse_grid(contents: &str) -> Vec<Vec<u32>> {
+    contents
+        .lines()
+        .map(|l| l.chars().map(|c| c.to_digit(10).unwrap()).collect())
+        .collect()
+}
+
+#[test]
+fn synchronise_test() {
+    let sample = parse_grid(
+        "5483143223\n2745854711\n5264556173\n6141336146\n6357385478\n\
+         4167524645\n2176841721\n6882881134\n4846848554\n5283751526",
+    );
+    let rules = Rules::standard();
+    let mut grid = sample.clone();
+    let flashes: usize = (0..100).map(|_| grid.step(&rules)).sum();
+    assert_eq!(flashes, 1656);
+    assert_eq!(synchronise(&sample, &rules, 1000), Synchronisation::At(195));
+    assert_eq!(
+        synchronise(&sample, &rules, 100),
+        Synchronisation::Unknown { steps: 100 }
+    );
+
+    // Two cells on a torus charge each other but never flash together.
+    let rules = Rules {
+        edges: Edges::Toroidal,
+        ..Rules::standard()
+    };
+    let pair = parse_grid("29");
+    let cycle = Cycle {
+        start: 0,
+        period: 9,
+    };
+    assert_eq!(
+        synchronise(&pair, &rules, 1000),
+        Synchronisation::Never(cycle)
+    );
+    assert_eq!(find_cycle(&pair, &rules, 1000), Some(cycle));
+    assert_eq!(find_cycle(&pair, &rules, 5), None);
+
+    let rules = Rules {
+        neighbourhood: Neighbourhood::Four,
+        ..rules
+    };
+    assert_eq!(
+        synchronise(&parse_grid("80\n63"), &rules, 1000),
+        Synchronisation::Never(Cycle {
+            start: 0,
+            period: 8,
+        })
+    );
+}
+
+#[test]
+fn cascade_test() {
+    let grid = parse_grid("11111\n19991\n19191\n19991\n11111");
+    let rules = Rules::standard();
+    let trace = trace_step(&grid, &rules, 1);
+    assert_eq!(trace.len(), 9);
+    // The ring of nines ignites, and the centre only flashes once all eight
+    // of them have charged it.
+    let ignitions: Vec<&Flash> = trace.iter().filter(|f| f.trigger.is_none()).collect();
+    assert_eq!(ignitions.len(), 8);
+    assert!(ignitions.iter().all(|f| f.depth == 0));
+    let centre = trace.last().unwrap();
+    assert_eq!(centre.point, Point { x: 2, y: 2 });
+    assert_eq!(centre.depth, 1);
+    let trigger = trace[centre.trigger.unwrap()];
+    assert_eq!(trigger.trigger, None);
+    assert!(trigger.point.x.abs_diff(2) <= 1 && trigger.point.y.abs_diff(2) <= 1);
+
+    let sizes = cascade_sizes(&trace);
+    assert_eq!(sizes.len(), 8);
+    assert_eq!(
+        sizes.iter().map(|(_, size)| size).sum::<usize>(),
+        trace.len()
+    );
+    assert_eq!(
+        sizes.iter().find(|(_, size)| *size == 2).unwrap().0,
+        trigger.point
+    );
+
+    let mut after = grid.clone();
+    after.step(&rules);
+    assert_eq!(after, parse_grid("34543\n40004\n50005\n40004\n34543"));
+    assert!(trace_step(&grid, &rules, 2).is_empty());
+}
@@ -1,55 +1,416 @@
 use std::collections::{HashMap, HashSet};
-use std::fs;
+use std::fmt::Write as _;
+use std::{env, fs};
 #[derive(Debug)]
 struct Node<'a> {
     links: Vec<&'a str>,
 }
 
-trait Visitor<'a> {
-    fn traverse(&'a self, second_visit: bool) -> usize;
+fn is_small(name: &str) -> bool {
+    name.chars().next().unwrap().is_lowercase()
 }
-impl<'a> Visitor<'a> for HashMap<&'a str, Node<'a>> {
-    fn traverse(&'a self, second_visit: bool) -> usize {
-        let mut work_queue = vec![(vec!["start"], HashSet::from([("start")]), None)];
-        let mut count = 0;
 
-        while let Some((current_path, current_visited, visited_twice)) = work_queue.pop() {
-            let node = *current_path.iter().last().unwrap();
-            if node == "end" {
-                println!(
-                    "========> {} twice: {:?}",
-                    current_path.join(","),
-                    visited_twice
+/// Which paths count as valid. Small caves may be entered up to
+/// `max_visits` times, but at most `max_revisited` of them more than once.
+/// The start cave is never re-entered and a path ends when it reaches `end`.
+#[derive(Debug, Clone)]
+struct VisitPolicy<'a> {
+    start: &'a str,
+    end: &'a str,
+    max_visits: u32,
+    max_revisited: u32,
+    /// Caves a path may not enter.
+    forbidden: Vec<&'a str>,
+    /// Caves every path has to pass through.
+    mandatory: Vec<&'a str>,
+}
+
+impl<'a> VisitPolicy<'a> {
+    /// Every small cave at most once.
+    fn part1() -> Self {
+        VisitPolicy {
+            start: "start",
+            end: "end",
+            max_visits: 1,
+            max_revisited: 0,
+            forbidden: Vec::new(),
+            mandatory: Vec::new(),
+        }
+    }
+
+    /// A single small cave may be visited twice.
+    fn part2() -> Self {
+        VisitPolicy {
+            max_visits: 2,
+            max_revisited: 1,
+            ..Self::part1()
+        }
+    }
+}
+
+/// The cave graph with caves numbered, so that the state of a partial path
+/// fits in a few integers and can be memoised.
+struct CaveGraph<'a> {
+    names: Vec<&'a str>,
+    small: Vec<bool>,
+    links: Vec<Vec<usize>>,
+}
+
+impl<'a> CaveGraph<'a> {
+    fn new(nodes: &HashMap<&'a str, Node<'a>>) -> Self {
+        let mut names: Vec<&str> = nodes.keys().copied().collect();
+        names.sort_unstable();
+        let index = |name: &str| names.iter().position(|n| *n == name).unwrap();
+        let links: Vec<Vec<usize>> = names
+            .iter()
+            .map(|name| nodes[name].links.iter().map(|l| index(l)).collect())
+            .collect();
+        let small: Vec<bool> = names.iter().map(|n| is_small(n)).collect();
+        for (cave, links) in links.iter().enumerate() {
+            if let Some(&other) = links.iter().find(|&&l| !small[cave] && !small[l]) {
+                panic!(
+                    "big caves {} and {} are linked, there are infinitely many paths",
+                    names[cave], names[other]
                 );
-                count += 1;
-                continue;
             }
-            for link in &self[node].links {
-                if *link == "start" {
-                    continue;
+        }
+        CaveGraph {
+            names,
+            small,
+            links,
+        }
+    }
+
+    fn index(&self, name: &str) -> usize {
+        self.names
+            .iter()
+            .position(|n| *n == name)
+            .unwrap_or_else(|| panic!("no cave named {name}"))
+    }
+
+    fn walker(&self, policy: &VisitPolicy) -> Walker<'_, 'a> {
+        assert!(policy.max_visits > 0, "small caves need at least one visit");
+        let bits = 32 - policy.max_visits.leading_zeros();
+        let mut slots = vec![None; self.names.len()];
+        let mut small_caves = 0;
+        for (cave, slot) in slots.iter_mut().enumerate() {
+            if self.small[cave] {
+                *slot = Some(small_caves * bits);
+                small_caves += 1;
+            }
+        }
+        assert!(
+            small_caves * bits <= 128,
+            "too many small caves to track {} visits each",
+            policy.max_visits
+        );
+        assert!(policy.mandatory.len() <= 64, "too many mandatory caves");
+        let mut mandatory = vec![None; self.names.len()];
+        for (bit, name) in policy.mandatory.iter().enumerate() {
+            mandatory[self.index(name)] = Some(bit);
+        }
+        Walker {
+            graph: self,
+            start: self.index(policy.start),
+            end: self.index(policy.end),
+            max_visits: policy.max_visits,
+            max_revisited: policy.max_revisited,
+            bits,
+            slots,
+            forbidden: policy.forbidden.iter().map(|n| self.index(n)).collect(),
+            mandatory,
+            all_mandatory: match policy.mandatory.len() {
+                0 => 0,
+                n => u64::MAX >> (64 - n),
+            },
+        }
+    }
+}
+
+/// State of a partial path: per small cave visit counts packed `bits` wide,
+/// how many small caves were entered more than once, and which mandatory
+/// caves were passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct WalkState {
+    cave: usize,
+    visits: u128,
+    revisited: u32,
+    mandatory: u64,
+}
+
+type PathMemo = HashMap<(usize, u128, u64), u128>;
+
+/// A cave graph together with a policy, resolved to cave indices.
+struct Walker<'g, 'a> {
+    graph: &'g CaveGraph<'a>,
+    start: usize,
+    end: usize,
+    max_visits: u32,
+    max_revisited: u32,
+    bits: u32,
+    slots: Vec<Option<u32>>,
+    forbidden: Vec<usize>,
+    mandatory: Vec<Option<usize>>,
+    all_mandatory: u64,
+}
+
+impl<'g, 'a> Walker<'g, 'a> {
+    fn initial(&self) -> WalkState {
+        WalkState {
+            cave: self.start,
+            visits: 0,
+            revisited: 0,
+            mandatory: 0,
+        }
+        .entered(self, self.start)
+    }
+
+    /// The state after moving on to `next`, if the policy allows it.
+    fn step(&self, state: &WalkState, next: usize) -> Option<WalkState> {
+        if next == self.start || self.forbidden.contains(&next) {
+            return None;
+        }
+        let mut state = *state;
+        if let Some(offset) = self.slots[next] {
+            let visits = (state.visits >> offset) as u32 & ((1 << self.bits) - 1);
+            if visits == self.max_visits {
+                return None;
+            }
+            if visits == 1 {
+                if state.revisited == self.max_revisited {
+                    return None;
                 }
-                let mut new_visited_twice: Option<&str> = visited_twice;
-                if link.chars().next().unwrap().is_lowercase() && current_visited.contains(link) {
-                    if second_visit && visited_twice.is_none() {
-                        new_visited_twice = Some(link)
-                    } else {
-                        continue;
+                state.revisited += 1;
+            }
+        }
+        Some(state.entered(self, next))
+    }
+
+    fn is_complete(&self, state: &WalkState) -> bool {
+        state.cave == self.end && state.mandatory == self.all_mandatory
+    }
+
+    /// Counts the valid paths without listing them.
+    fn count(&self) -> u128 {
+        self.count_from(self.initial(), &mut PathMemo::new())
+    }
+
+    fn count_from(&self, state: WalkState, memo: &mut PathMemo) -> u128 {
+        if state.cave == self.end {
+            return self.is_complete(&state) as u128;
+        }
+        let key = (state.cave, state.visits, state.mandatory);
+        if let Some(&count) = memo.get(&key) {
+            return count;
+        }
+        let count = self.graph.links[state.cave]
+            .iter()
+            .filter_map(|&next| self.step(&state, next))
+            .map(|next| self.count_from(next, memo))
+            .sum();
+        memo.insert(key, count);
+        count
+    }
+
+    /// How many valid paths use each tunnel, keyed by the ordered pair of cave
+    /// indices. Found by multiplying the number of ways to reach a state with
+    /// the number of ways to finish from the next one, so nothing is listed.
+    fn edge_usage(&self) -> HashMap<(usize, usize), u128> {
+        let mut memo = PathMemo::new();
+        let mut states = HashSet::new();
+        let mut stack = vec![self.initial()];
+        while let Some(state) = stack.pop() {
+            if state.cave == self.end || !states.insert(state) {
+                continue;
+            }
+            stack.extend(
+                self.graph.links[state.cave]
+                    .iter()
+                    .filter_map(|&next| self.step(&state, next)),
+            );
+        }
+        // Entering a small cave adds a visit and big caves only link to small
+        // ones, so this order puts every state after all of its predecessors.
+        let mut states: Vec<WalkState> = states.into_iter().collect();
+        states.sort_by_key(|s| (self.total_visits(s), !self.graph.small[s.cave]));
+
+        let mut prefixes = HashMap::from([(self.initial(), 1u128)]);
+        let mut usage = HashMap::new();
+        for state in states {
+            let prefix = prefixes.get(&state).copied().unwrap_or(0);
+            for &next in &self.graph.links[state.cave] {
+                if let Some(next_state) = self.step(&state, next) {
+                    *prefixes.entry(next_state).or_default() += prefix;
+                    let paths = prefix * self.count_from(next_state, &mut memo);
+                    if paths > 0 {
+                        let edge = (state.cave.min(next), state.cave.max(next));
+                        *usage.entry(edge).or_default() += paths;
                     }
                 }
-                let mut new_path = current_path.to_owned();
-                new_path.push(link);
-                let mut new_counts = current_visited.to_owned();
-                new_counts.insert(node);
-                work_queue.push((new_path, new_counts, new_visited_twice));
             }
         }
+        usage
+    }
 
-        count
+    fn total_visits(&self, state: &WalkState) -> u32 {
+        let mask = (1 << self.bits) - 1;
+        self.slots
+            .iter()
+            .flatten()
+            .map(|&offset| (state.visits >> offset) as u32 & mask)
+            .sum()
+    }
+
+    /// The first path `list` would return, found by only entering states
+    /// from which some valid path continues.
+    fn first(&self) -> Option<Vec<&'a str>> {
+        let mut memo = PathMemo::new();
+        let mut state = self.initial();
+        if self.count_from(state, &mut memo) == 0 {
+            return None;
+        }
+        let mut path = vec![self.graph.names[state.cave]];
+        while state.cave != self.end {
+            state = self.graph.links[state.cave]
+                .iter()
+                .filter_map(|&next| self.step(&state, next))
+                .find(|next| self.count_from(*next, &mut memo) > 0)
+                .unwrap();
+            path.push(self.graph.names[state.cave]);
+        }
+        Some(path)
+    }
+
+    /// Lists every valid path. Only feasible when there are few of them.
+    fn list(&self) -> Vec<Vec<&'a str>> {
+        let mut paths = Vec::new();
+        let mut path = vec![self.start];
+        self.list_from(self.initial(), &mut path, &mut paths);
+        paths
+    }
+
+    fn list_from(&self, state: WalkState, path: &mut Vec<usize>, paths: &mut Vec<Vec<&'a str>>) {
+        if state.cave == self.end {
+            if self.is_complete(&state) {
+                paths.push(path.iter().map(|&c| self.graph.names[c]).collect());
+            }
+            return;
+        }
+        for &next in &self.graph.links[state.cave] {
+            if let Some(next_state) = self.step(&state, next) {
+                path.push(next);
+                self.list_from(next_state, path, paths);
+                path.pop();
+            }
+        }
     }
 }
-fn main() {
+
+/// What to emphasise in the DOT rendering of the cave graph.
+enum Highlight<'a> {
+    Nothing,
+    /// A single path, given by cave names.
+    Path(Vec<&'a str>),
+    /// Tunnel usage counts from `Walker::edge_usage`.
+    Usage(HashMap<(usize, usize), u128>),
+}
+
+impl<'a> CaveGraph<'a> {
+    /// Graphviz rendering: big caves as boxes, small caves as circles and
+    /// the highlighted path or tunnel usage in red.
+    fn to_dot(&self, highlight: &Highlight) -> String {
+        let mut dot = String::from("graph caves {\n");
+        let on_path: Vec<(&str, &str)> = match highlight {
+            Highlight::Path(path) => path.windows(2).map(|w| (w[0], w[1])).collect(),
+            _ => Vec::new(),
+        };
+        for (cave, name) in self.names.iter().enumerate() {
+            let shape = if self.small[cave] {
+                "shape=circle"
+            } else {
+                "shape=box, style=filled, fillcolor=lightgrey"
+            };
+            let colour = match highlight {
+                Highlight::Path(path) if path.contains(name) => ", color=red, fontcolor=red",
+                _ => "",
+            };
+            writeln!(dot, "    \"{name}\" [{shape}{colour}];").unwrap();
+        }
+        let max_usage = match highlight {
+            Highlight::Usage(usage) => usage.values().copied().max().unwrap_or(0),
+            _ => 0,
+        };
+        for (a, links) in self.links.iter().enumerate() {
+            for &b in links.iter().filter(|&&b| a < b) {
+                let (from, to) = (self.names[a], self.names[b]);
+                let style = match highlight {
+                    Highlight::Nothing => String::new(),
+                    Highlight::Path(_) => {
+                        let used = on_path.contains(&(from, to)) || on_path.contains(&(to, from));
+                        if used {
+                            " [color=red, penwidth=3]".to_string()
+                        } else {
+                            String::new()
+                        }
+                    }
+                    Highlight::Usage(usage) => {
+                        let used = usage.get(&(a, b)).copied().unwrap_or(0);
+                        let width = 1.0 + 7.0 * used as f64 / max_usage.max(1) as f64;
+                        format!(" [label=\"{used}\", penwidth={width:.2}, color=red]")
+                    }
+                };
+                writeln!(dot, "    \"{from}\" -- \"{to}\"{style};").unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl WalkState {
+    fn entered(mut self, walker: &Walker, cave: usize) -> Self {
+        self.cave = cave;
+        if let Some(offset) = walker.slots[cave] {
+            self.visits += 1 << offset;
+        }
+        if let Some(bit) = walker.mandatory[cave] {
+            self.mandatory |= 1 << bit;
+        }
+        self
+    }
+}
+
+/// Builds a policy from `--max-visits N`, `--max-revisited N`,
+/// `--forbid CAVE` and `--require CAVE`, the last two repeatable. Returns
+/// `None` if none of them is given, and panics on any other option except
+/// `--list` and `--export`.
+fn policy_from_args(args: &[String]) -> Option<VisitPolicy<'_>> {
+    let mut policy = VisitPolicy::part1();
+    let mut given = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            given = true;
+            args.next()
+                .unwrap_or_else(|| panic!("{arg} needs a value"))
+                .as_str()
+        };
+        let number = |v: &str| v.parse().unwrap_or_else(|_| panic!("{arg}: not a number"));
+        match arg.as_str() {
+            "--max-visits" => policy.max_visits = number(value()),
+            "--max-revisited" => policy.max_revisited = number(value()),
+            "--forbid" => policy.forbidden.push(value()),
+            "--require" => policy.mandatory.push(value()),
+            "--list" | "--export" => {}
+            other if other.starts_with("--") => panic!("unknown option {other}"),
+            _ => {}
+        }
+    }
+    given.then_some(policy)
+}
+
+fn parse_nodes(contents: &str) -> HashMap<&str, Node<'_>> {
     let mut nodes = HashMap::new();
-    let contents = fs::read_to_string("day12/input").expect("could not read input");
     for line in contents.lines() {
         let (from, to) = line.split_once('-').unwrap();
         let node = nodes.entry(from).or_insert(Node { links: Vec::new() });
@@ -57,9 +418,110 @@ fn main() {
         let node = nodes.entry(to).or_insert(Node { links: Vec::new() });
         node.links.push(from);
     }
+    nodes
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let constrained = policy_from_args(&args);
+    let contents = fs::read_to_string("day12/input").expect("could not read input");
+    let nodes = parse_nodes(&contents);
     println!("{:?}", nodes);
-    let count = nodes.traverse(false);
-    println!("paths 1: {}", count);
-    let count = nodes.traverse(true);
-    println!("paths 2: {}", count);
+
+    let graph = CaveGraph::new(&nodes);
+    let export = args.iter().any(|a| a == "--export");
+    if export {
+        fs::write("day12/caves.dot", graph.to_dot(&Highlight::Nothing))
+            .expect("could not write graph");
+        if let Some(path) = graph.walker(&VisitPolicy::part1()).first() {
+            let dot = graph.to_dot(&Highlight::Path(path));
+            fs::write("day12/first_path.dot", dot).expect("could not write graph");
+        }
+    }
+    let list = args.iter().any(|a| a == "--list");
+    let mut policies = vec![
+        ("paths 1", VisitPolicy::part1()),
+        ("paths 2", VisitPolicy::part2()),
+    ];
+    if let Some(policy) = constrained {
+        policies.push(("constrained paths", policy));
+    }
+    for (name, policy) in policies {
+        let walker = graph.walker(&policy);
+        println!("{name}: {}", walker.count());
+        if export {
+            let usage = walker.edge_usage();
+            let file = format!("day12/{}.dot", name.replace(' ', "_"));
+            fs::write(file, graph.to_dot(&Highlight::Usage(usage))).expect("could not write graph");
+        }
+        // Listing every path is only feasible on small graphs.
+        if list {
+            for path in walker.list() {
+                println!("========> {}", path.join(","));
+            }
+        }
+    }
+}
+
+/// Checks the memoised walker against plain enumeration of every path.
+#[cfg(test)]
+fn check_walker(graph: &CaveGraph, policy: &VisitPolicy) -> usize {
+    let walker = graph.walker(policy);
+    let paths = walker.list();
+    assert_eq!(walker.count(), paths.len() as u128, "{policy:?}");
+    assert_eq!(walker.first(), paths.first().cloned(), "{policy:?}");
+    let mut usage: HashMap<(usize, usize), u128> = HashMap::new();
+    for path in &paths {
+        for pair in path.windows(2) {
+            let (a, b) = (graph.index(pair[0]), graph.index(pair[1]));
+            *usage.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    assert_eq!(walker.edge_usage(), usage, "{policy:?}");
+    paths.len()
+}
+
+#[test]
+fn walker_test() {
+    for (contents, counts) in [
+        (include_str!("../input_simple"), [10, 36]),
+        (include_str!("../input_simple2"), [19, 103]),
+    ] {
+        let nodes = parse_nodes(contents);
+        let graph = CaveGraph::new(&nodes);
+        assert_eq!(check_walker(&graph, &VisitPolicy::part1()), counts[0]);
+        assert_eq!(check_walker(&graph, &VisitPolicy::part2()), counts[1]);
+    }
+}
+
+#[test]
+fn constrained_walker_test() {
+    let nodes = parse_nodes(include_str!("../input_simple"));
+    let graph = CaveGraph::new(&nodes);
+    let args = |line: &str| -> Vec<String> { line.split(' ').map(String::from).collect() };
+    let args = args("--max-visits 2 --max-revisited 2 --forbid d --require c --list --export");
+    let policy = policy_from_args(&args).unwrap();
+    assert_eq!(
+        (policy.max_visits, policy.max_revisited),
+        (2, 2),
+        "{policy:?}"
+    );
+    let count = check_walker(&graph, &policy);
+    assert!(count > 0);
+    for path in graph.walker(&policy).list() {
+        assert!(path.contains(&"c") && !path.contains(&"d"), "{path:?}");
+    }
+    let unconstrained = VisitPolicy {
+        max_visits: 2,
+        max_revisited: 2,
+        ..VisitPolicy::part1()
+    };
+    assert!(check_walker(&graph, &unconstrained) > count);
+    assert!(policy_from_args(&["--list".to_string()]).is_none());
+}
+
+#[test]
+#[should_panic(expected = "unknown option --forbidd")]
+fn unknown_option_test() {
+    policy_from_args(&["--forbidd".to_string(), "kw".to_string()]);
 }
@@ -12,6 +12,209 @@ enum Fold {
     Y(i32),
 }
 
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum FoldError {
+    /// Dots on the fold line have nowhere to go.
+    DotOnFoldLine(Point),
+    /// The fold line lies left of or above the sheet.
+    OutsidePaper,
+}
+
+/// A transparent sheet of `width` x `height` cells with dots on it.
+struct Paper {
+    dots: HashSet<Point>,
+    width: i32,
+    height: i32,
+}
+
+/// Where `v` ends up when folding a strip of `len` cells at `line`, and the
+/// length of the folded strip. The folded strip starts at the far end of the
+/// longer part, so folds past the midpoint shift the origin instead of
+/// producing negative coordinates.
+fn fold_coordinate(v: i32, line: i32, len: i32) -> (i32, i32) {
+    let before = line;
+    let after = len - line - 1;
+    let shift = (after - before).max(0);
+    let v = if v > line { 2 * line - v } else { v };
+    (v + shift, before.max(after))
+}
+
+impl Paper {
+    /// The sheet is taken to be just big enough for all dots. The real sheet
+    /// may be larger, so a fold beyond its edge only cuts off empty cells.
+    fn new(dots: HashSet<Point>) -> Self {
+        let width = dots.iter().map(|p| p.x + 1).max().unwrap_or(0);
+        let height = dots.iter().map(|p| p.y + 1).max().unwrap_or(0);
+        Paper {
+            dots,
+            width,
+            height,
+        }
+    }
+
+    fn fold(&mut self, fold: Fold) -> Result<(), FoldError> {
+        let (line, len) = match fold {
+            Fold::X(x) => (x, self.width),
+            Fold::Y(y) => (y, self.height),
+        };
+        if line < 0 {
+            return Err(FoldError::OutsidePaper);
+        }
+        let on_line = |p: &&Point| match fold {
+            Fold::X(x) => p.x == x,
+            Fold::Y(y) => p.y == y,
+        };
+        if let Some(p) = self.dots.iter().find(on_line) {
+            return Err(FoldError::DotOnFoldLine(*p));
+        }
+        self.dots = self
+            .dots
+            .iter()
+            .map(|p| match fold {
+                Fold::X(x) => Point {
+                    x: fold_coordinate(p.x, x, len).0,
+                    y: p.y,
+                },
+                Fold::Y(y) => Point {
+                    x: p.x,
+                    y: fold_coordinate(p.y, y, len).0,
+                },
+            })
+            .collect();
+        let new_len = fold_coordinate(0, line, len).1;
+        match fold {
+            Fold::X(_) => self.width = new_len,
+            Fold::Y(_) => self.height = new_len,
+        }
+        Ok(())
+    }
+
+    fn dot_count(&self) -> usize {
+        self.dots.len()
+    }
+
+    /// The sheet as lines of `#` for dots and spaces for empty cells.
+    fn render(&self) -> String {
+        let mut sheet = String::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                sheet.push(if self.dots.contains(&Point { x, y }) {
+                    '#'
+                } else {
+                    ' '
+                });
+            }
+            sheet.push('\n');
+        }
+        sheet
+    }
+}
+
+/// A fixed-pitch block font: glyphs are `width` x `height` cells, one every
+/// `pitch` columns, drawn with `#` and `.`.
+struct Font {
+    width: usize,
+    height: usize,
+    pitch: usize,
+    glyphs: &'static [(char, &'static [&'static str])],
+}
+
+#[rustfmt::skip]
+const FONT_4X6: Font = Font {
+    width: 4,
+    height: 6,
+    pitch: 5,
+    glyphs: &[
+        ('A', &[".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+        ('B', &["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+        ('C', &[".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+        ('E', &["####", "#...", "###.", "#...", "#...", "####"]),
+        ('F', &["####", "#...", "###.", "#...", "#...", "#..."]),
+        ('G', &[".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+        ('H', &["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+        ('I', &[".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+        ('J', &["..##", "...#", "...#", "...#", "#..#", ".##."]),
+        ('K', &["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+        ('L', &["#...", "#...", "#...", "#...", "#...", "####"]),
+        ('O', &[".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+        ('P', &["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+        ('R', &["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+        ('S', &[".###", "#...", "#...", ".##.", "...#", "###."]),
+        ('U', &["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+        ('Z', &["####", "...#", "..#.", ".#..", "#...", "####"]),
+    ],
+};
+
+#[rustfmt::skip]
+const FONT_6X10: Font = Font {
+    width: 6,
+    height: 10,
+    pitch: 8,
+    glyphs: &[
+        ('A', &["..##..", ".#..#.", "#....#", "#....#", "#....#", "######", "#....#", "#....#", "#....#", "#....#"]),
+        ('B', &["#####.", "#....#", "#....#", "#....#", "#####.", "#....#", "#....#", "#....#", "#....#", "#####."]),
+        ('C', &[".####.", "#....#", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#....#", ".####."]),
+        ('E', &["######", "#.....", "#.....", "#.....", "#####.", "#.....", "#.....", "#.....", "#.....", "######"]),
+        ('F', &["######", "#.....", "#.....", "#.....", "#####.", "#.....", "#.....", "#.....", "#.....", "#....."]),
+        ('G', &[".####.", "#....#", "#.....", "#.....", "#.....", "#..###", "#....#", "#....#", "#...##", ".###.#"]),
+        ('H', &["#....#", "#....#", "#....#", "#....#", "######", "#....#", "#....#", "#....#", "#....#", "#....#"]),
+        ('J', &["...###", "....#.", "....#.", "....#.", "....#.", "....#.", "....#.", "#...#.", "#...#.", ".###.."]),
+        ('K', &["#....#", "#...#.", "#..#..", "#.#...", "##....", "##....", "#.#...", "#..#..", "#...#.", "#....#"]),
+        ('L', &["#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "######"]),
+        ('N', &["#....#", "##...#", "##...#", "#.#..#", "#.#..#", "#..#.#", "#..#.#", "#...##", "#...##", "#....#"]),
+        ('P', &["#####.", "#....#", "#....#", "#....#", "#####.", "#.....", "#.....", "#.....", "#.....", "#....."]),
+        ('R', &["#####.", "#....#", "#....#", "#....#", "#####.", "#..#..", "#...#.", "#...#.", "#....#", "#....#"]),
+        ('X', &["#....#", "#....#", ".#..#.", ".#..#.", "..##..", "..##..", ".#..#.", ".#..#.", "#....#", "#....#"]),
+        ('Z', &["######", ".....#", ".....#", "....#.", "...#..", "..#...", ".#....", "#.....", "#.....", "######"]),
+    ],
+};
+
+/// A glyph that matches no letter of the font, with the column it starts at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UnknownGlyph {
+    column: i32,
+    rows: Vec<String>,
+}
+
+/// Reads the letters drawn by `points`, with the first glyph at column 0.
+fn recognise(points: &HashSet<Point>, font: &Font) -> Result<String, Vec<UnknownGlyph>> {
+    let Some(max_x) = points.iter().map(|p| p.x).max() else {
+        return Ok(String::new());
+    };
+    let count = (max_x as usize + font.pitch) / font.pitch;
+    let mut text = String::new();
+    let mut unknown = Vec::new();
+    for i in 0..count {
+        let column = (i * font.pitch) as i32;
+        let rows: Vec<String> = (0..font.height as i32)
+            .map(|y| {
+                (column..column + font.width as i32)
+                    .map(|x| {
+                        if points.contains(&Point { x, y }) {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let glyph = font
+            .glyphs
+            .iter()
+            .find(|(_, pattern)| pattern[..] == rows[..]);
+        match glyph {
+            Some((letter, _)) => text.push(*letter),
+            None => unknown.push(UnknownGlyph { column, rows }),
+        }
+    }
+    if unknown.is_empty() {
+        Ok(text)
+    } else {
+        Err(unknown)
+    }
+}
+
 fn main() {
     let contents = fs::read_to_string("day13/input").expect("could not read input");
     let mut points = HashSet::new();
@@ -31,51 +234,90 @@ fn main() {
             });
         }
     }
-    for fold in folds {
-        points = points
-            .iter()
-            .map(|p| match fold {
-                Fold::X(x) => {
-                    if p.x > x {
-                        Point {
-                            x: x - (p.x - x),
-                            y: p.y,
-                        }
-                    } else {
-                        *p
-                    }
+    let mut paper = Paper::new(points);
+    for (i, fold) in folds.into_iter().enumerate() {
+        if let Err(e) = paper.fold(fold) {
+            panic!("fold {} ({:?}) failed: {:?}", i + 1, fold, e);
+        }
+        println!(
+            "after fold {} ({:?}): {} dots on {}x{}",
+            i + 1,
+            fold,
+            paper.dot_count(),
+            paper.width,
+            paper.height
+        );
+    }
+    let points = &paper.dots;
+    let font = [&FONT_4X6, &FONT_6X10]
+        .into_iter()
+        .find(|f| f.height as i32 == paper.height);
+    match font.map(|font| recognise(points, font)) {
+        None => println!("no font is {} cells high", paper.height),
+        Some(Ok(text)) => println!("code {text}"),
+        Some(Err(unknown)) => {
+            for glyph in unknown {
+                println!("unrecognised glyph at column {}:", glyph.column);
+                for row in glyph.rows {
+                    println!("{row}");
                 }
-                Fold::Y(y) => {
-                    if p.y > y {
-                        Point {
-                            x: p.x,
-                            y: y - (p.y - y),
-                        }
-                    } else {
-                        *p
+            }
+        }
+    }
+
+    print!("{}", paper.render());
+}
+
+#[test]
+fn fold_beyond_dots_test() {
+    let mut paper = Paper::new(HashSet::from([Point { x: 0, y: 0 }, Point { x: 3, y: 1 }]));
+    assert_eq!(paper.fold(Fold::Y(7)), Ok(()));
+    assert_eq!((paper.width, paper.height, paper.dot_count()), (4, 7, 2));
+    assert_eq!(paper.fold(Fold::X(2)), Ok(()));
+    assert_eq!((paper.width, paper.height, paper.dot_count()), (2, 7, 2));
+    assert!(paper.dots.contains(&Point { x: 1, y: 1 }));
+    assert_eq!(paper.fold(Fold::X(-1)), Err(FoldError::OutsidePaper));
+}
+
+#[test]
+fn recognise_test() {
+    let draw = |font: &Font, glyphs: &[&[&str]]| -> HashSet<Point> {
+        let mut points = HashSet::new();
+        for (i, rows) in glyphs.iter().enumerate() {
+            for (y, row) in rows.iter().enumerate() {
+                for (x, c) in row.chars().enumerate() {
+                    if c == '#' {
+                        points.insert(Point {
+                            x: (i * font.pitch + x) as i32,
+                            y: y as i32,
+                        });
                     }
                 }
-            })
-            .collect();
-    }
-    let mut printout: Vec<Vec<bool>> = Vec::new();
-    for point in points {
-        if point.y as usize >= printout.len() {
-            printout.resize(point.y as usize + 1, Vec::new());
+            }
         }
-        if point.x as usize >= printout[point.y as usize].len() {
-            printout[point.y as usize].resize(point.x as usize + 1, false);
-        }
-        printout[point.y as usize][point.x as usize] = true;
-    }
+        points
+    };
+    let glyph = |font: &Font, letter: char| font.glyphs.iter().find(|g| g.0 == letter).unwrap().1;
 
-    for line in printout {
-        println!(
-            "{}",
-            line.iter().map(|v| match v {
-                true => "#",
-                false => " ",
-            }).collect::<String>()
-        )
-    }
+    let points = draw(&FONT_4X6, &[glyph(&FONT_4X6, 'R'), glyph(&FONT_4X6, 'G')]);
+    assert_eq!(recognise(&points, &FONT_4X6), Ok("RG".to_string()));
+    let points = draw(
+        &FONT_6X10,
+        &[glyph(&FONT_6X10, 'X'), glyph(&FONT_6X10, 'N')],
+    );
+    assert_eq!(recognise(&points, &FONT_6X10), Ok("XN".to_string()));
+    assert_eq!(recognise(&HashSet::new(), &FONT_4X6), Ok(String::new()));
+
+    let block: &[&str] = &["####"; 6];
+    let points = draw(
+        &FONT_4X6,
+        &[glyph(&FONT_4X6, 'Z'), glyph(&FONT_4X6, 'L'), block],
+    );
+    assert_eq!(
+        recognise(&points, &FONT_4X6),
+        Err(vec![UnknownGlyph {
+            column: 10,
+            rows: vec!["####".to_string(); 6],
+        }])
+    );
 }
@@ -1,46 +1,268 @@
-use std::{collections::HashMap, fs};
+use num::{BigUint, One, Zero};
+use std::fs;
+use std::ops::AddAssign;
 
-fn substitute<'a>(
-    input: &'a str,
-    patterns: &[(&str, &str)],
-    depth: usize,
-    cache: &'a mut HashMap<(String, usize), HashMap<char, usize>>,
-) -> HashMap<char, usize> {
-    if let Some(entry) = cache.get(&(input.to_string(), depth)) {
-        return entry.clone();
+/// Insertion rules over numbered elements. Pair `(a, b)` is stored at index
+/// `a * n + b`, where `n` is the number of distinct elements.
+struct Polymer {
+    elements: Vec<char>,
+    template: Vec<usize>,
+    /// Element inserted between each pair, if any rule matches it.
+    insertions: Vec<Option<usize>>,
+}
+
+/// Pair and element counts of a polymer after some number of steps.
+#[derive(Debug, Clone)]
+struct PairCounts<C> {
+    pairs: Vec<C>,
+    elements: Vec<C>,
+}
+
+impl Polymer {
+    /// When several rules match the same pair, the first one wins.
+    fn new(template: &str, patterns: &[(&str, &str)]) -> Self {
+        let mut elements: Vec<char> = template
+            .chars()
+            .chain(
+                patterns
+                    .iter()
+                    .flat_map(|(p, i)| p.chars().chain(i.chars())),
+            )
+            .collect();
+        elements.sort_unstable();
+        elements.dedup();
+        let id = |c: char| elements.binary_search(&c).unwrap();
+        let n = elements.len();
+        let mut insertions = vec![None; n * n];
+        for (pattern, insertion) in patterns {
+            let mut pair = pattern.chars();
+            let (a, b) = (id(pair.next().unwrap()), id(pair.next().unwrap()));
+            let slot = &mut insertions[a * n + b];
+            if slot.is_none() {
+                *slot = Some(id(insertion.chars().next().unwrap()));
+            }
+        }
+        Polymer {
+            template: template.chars().map(id).collect(),
+            elements,
+            insertions,
+        }
     }
 
-    let mut result_counts = HashMap::new();
-    for (pattern, insertion) in patterns {
-        if input == *pattern {
-            let result = String::new() + &input[0..1] + *insertion + &input[1..];
-            *result_counts
-                .entry(insertion.chars().next().unwrap())
-                .or_default() += 1;
-            if depth != 0 {
-                merge(
-                    &mut result_counts,
-                    substitute(&result[0..2], patterns, depth - 1, cache),
-                );
-                merge(
-                    &mut result_counts,
-                    substitute(&result[1..3], patterns, depth - 1, cache),
-                );
+    fn pair_index(&self, a: usize, b: usize) -> usize {
+        a * self.elements.len() + b
+    }
+
+    fn initial<C: Clone + Zero + One + for<'a> AddAssign<&'a C>>(&self) -> PairCounts<C> {
+        let n = self.elements.len();
+        let mut counts = PairCounts {
+            pairs: vec![C::zero(); n * n],
+            elements: vec![C::zero(); n],
+        };
+        for &e in &self.template {
+            counts.elements[e] += &C::one();
+        }
+        for pair in self.template.windows(2) {
+            counts.pairs[self.pair_index(pair[0], pair[1])] += &C::one();
+        }
+        counts
+    }
+
+    /// Applies the transition matrix once: a pair with an insertion `c`
+    /// moves its count to `(a, c)` and `(c, b)`, any other pair keeps it.
+    fn step<C: Clone + Zero + for<'a> AddAssign<&'a C>>(
+        &self,
+        counts: &PairCounts<C>,
+    ) -> PairCounts<C> {
+        let n = self.elements.len();
+        let mut next = PairCounts {
+            pairs: vec![C::zero(); n * n],
+            elements: counts.elements.clone(),
+        };
+        for (pair, count) in counts.pairs.iter().enumerate() {
+            if count.is_zero() {
+                continue;
+            }
+            match self.insertions[pair] {
+                Some(c) => {
+                    let (a, b) = (pair / n, pair % n);
+                    next.pairs[self.pair_index(a, c)] += count;
+                    next.pairs[self.pair_index(c, b)] += count;
+                    next.elements[c] += count;
+                }
+                None => next.pairs[pair] += count,
             }
+        }
+        next
+    }
 
-            break;
+    /// Element counts after every step from 0 to `depth`.
+    fn element_history<C: Clone + Zero + One + for<'a> AddAssign<&'a C>>(
+        &self,
+        depth: usize,
+    ) -> Vec<Vec<C>> {
+        let mut counts = self.initial();
+        let mut history = vec![counts.elements.clone()];
+        for _ in 0..depth {
+            counts = self.step(&counts);
+            history.push(counts.elements.clone());
         }
+        history
+    }
+
+    fn after<C: Clone + Zero + One + for<'a> AddAssign<&'a C>>(
+        &self,
+        depth: usize,
+    ) -> PairCounts<C> {
+        (0..depth).fold(self.initial(), |counts, _| self.step(&counts))
     }
-    cache.insert((input.to_string(), depth), result_counts.clone());
-    result_counts
 }
 
-fn merge(a: &mut HashMap<char, usize>, b: HashMap<char, usize>) {
-    for (ch, cnt) in b {
-        *a.entry(ch).or_default() += cnt;
+impl Polymer {
+    /// Builds the actual polymer string. Its length is
+    /// `(template.len() - 1) * 2^depth + 1`, so only small depths are feasible.
+    fn materialise(&self, depth: usize) -> Vec<usize> {
+        let n = self.elements.len();
+        let mut polymer = self.template.clone();
+        for _ in 0..depth {
+            let mut next = Vec::with_capacity(polymer.len() * 2);
+            next.push(polymer[0]);
+            for pair in polymer.windows(2) {
+                if let Some(c) = self.insertions[pair[0] * n + pair[1]] {
+                    next.push(c);
+                }
+                next.push(pair[1]);
+            }
+            polymer = next;
+        }
+        polymer
+    }
+
+    fn render(&self, polymer: &[usize]) -> String {
+        polymer.iter().map(|&e| self.elements[e]).collect()
+    }
+
+    /// Materialises the polymer and checks that its element and pair counts
+    /// agree with the pair-count engine.
+    #[cfg(test)]
+    fn cross_check(&self, depth: usize) -> Result<String, String> {
+        let polymer = self.materialise(depth);
+        let mut actual = PairCounts {
+            pairs: vec![0u128; self.insertions.len()],
+            elements: vec![0u128; self.elements.len()],
+        };
+        for &e in &polymer {
+            actual.elements[e] += 1;
+        }
+        for pair in polymer.windows(2) {
+            actual.pairs[self.pair_index(pair[0], pair[1])] += 1;
+        }
+        let expected = self.after::<u128>(depth);
+        if actual.elements != expected.elements {
+            Err(format!(
+                "element counts differ after {depth} steps: {:?} vs {:?}",
+                actual.elements, expected.elements
+            ))
+        } else if actual.pairs != expected.pairs {
+            Err(format!("pair counts differ after {depth} steps"))
+        } else {
+            Ok(self.render(&polymer))
+        }
     }
 }
 
+/// Problems with a set of insertion rules. Pairs without a rule are left
+/// unchanged and the first of several rules for a pair wins, so none of
+/// these are fatal, but they usually point at a broken rule set.
+#[derive(Debug, Default)]
+struct RuleReport {
+    /// Pairs over the alphabet of the template and the rules with no rule.
+    missing: Vec<String>,
+    /// Rules given more than once with the same insertion.
+    duplicates: Vec<String>,
+    /// Pairs with rules inserting different elements, in rule order.
+    conflicts: Vec<(String, Vec<char>)>,
+    /// Elements mentioned in the rules that no step can ever produce.
+    unreachable: Vec<char>,
+}
+
+impl RuleReport {
+    fn is_clean(&self) -> bool {
+        self.missing.is_empty()
+            && self.duplicates.is_empty()
+            && self.conflicts.is_empty()
+            && self.unreachable.is_empty()
+    }
+}
+
+fn check_rules(template: &str, patterns: &[(&str, &str)]) -> RuleReport {
+    let polymer = Polymer::new(template, patterns);
+    let mut report = RuleReport::default();
+
+    let mut by_pair: Vec<(&str, Vec<char>)> = Vec::new();
+    for (pattern, insertion) in patterns {
+        let insertion = insertion.chars().next().unwrap();
+        match by_pair.iter_mut().find(|(p, _)| p == pattern) {
+            Some((_, insertions)) => insertions.push(insertion),
+            None => by_pair.push((pattern, vec![insertion])),
+        }
+    }
+    for (pattern, insertions) in by_pair {
+        if insertions.iter().any(|&i| i != insertions[0]) {
+            report.conflicts.push((pattern.to_string(), insertions));
+        } else if insertions.len() > 1 {
+            report.duplicates.push(pattern.to_string());
+        }
+    }
+
+    let n = polymer.elements.len();
+    for (pair, insertion) in polymer.insertions.iter().enumerate() {
+        if insertion.is_none() {
+            let (a, b) = (polymer.elements[pair / n], polymer.elements[pair % n]);
+            report.missing.push(format!("{a}{b}"));
+        }
+    }
+
+    // Walk every pair reachable from the template to see which elements
+    // can be inserted at all.
+    let mut reachable = vec![false; n];
+    let mut seen = vec![false; n * n];
+    let mut stack: Vec<usize> = polymer
+        .template
+        .windows(2)
+        .map(|p| polymer.pair_index(p[0], p[1]))
+        .collect();
+    for &e in &polymer.template {
+        reachable[e] = true;
+    }
+    while let Some(pair) = stack.pop() {
+        if std::mem::replace(&mut seen[pair], true) {
+            continue;
+        }
+        if let Some(c) = polymer.insertions[pair] {
+            reachable[c] = true;
+            stack.push(polymer.pair_index(pair / n, c));
+            stack.push(polymer.pair_index(c, pair % n));
+        }
+    }
+    report.unreachable = polymer
+        .elements
+        .iter()
+        .zip(reachable)
+        .filter(|(_, reachable)| !reachable)
+        .map(|(&e, _)| e)
+        .collect();
+    report
+}
+
+/// Difference between the most and least common element that occurs.
+fn spread<C: Clone + Ord + Zero + std::ops::Sub<Output = C>>(elements: &[C]) -> C {
+    let present = || elements.iter().filter(|c| !c.is_zero());
+    let max = present().max().cloned().unwrap_or_else(C::zero);
+    let min = present().min().cloned().unwrap_or_else(C::zero);
+    max - min
+}
+
 fn main() {
     let contents = fs::read_to_string("day14/input").expect("could not read input");
     let mut lines = contents.lines();
@@ -52,29 +274,57 @@ fn main() {
     println!("template: {}", template);
     println!("patterns: {:?}", patterns);
 
-    let depth = 40;
-    let mut counts = template.chars().fold(HashMap::new(), |mut a, c| {
-        *a.entry(c).or_default() += 1;
-        a
-    });
-    let mut cache = HashMap::new();
-    for i in 0..template.len() - 1 {
-        merge(
-            &mut counts,
-            substitute(&template[i..i + 2], &patterns, depth - 1, &mut cache),
-        );
+    let report = check_rules(&template, &patterns);
+    if !report.is_clean() {
+        println!("rule problems: {:?}", report);
     }
-    println!("counts {:?}", counts);
 
-    let mut min = ('\0', usize::MAX);
-    let mut max = ('\0', usize::MIN);
-    for e in counts {
-        if e.1 > max.1 {
-            max = e;
-        }
-        if e.1 < min.1 {
-            min = e;
+    let polymer = Polymer::new(&template, &patterns);
+    for depth in 0..=4 {
+        println!("step {depth}: {}", polymer.render(&polymer.materialise(depth)));
+    }
+    let history = polymer.element_history::<u128>(40);
+    for (step, elements) in history.iter().enumerate() {
+        let counts: Vec<_> = polymer.elements.iter().zip(elements).collect();
+        println!("step {step} counts {:?}", counts);
+    }
+    println!("diff after 10: {}", spread(&history[10]));
+    println!("diff after 40: {}", spread(&history[40]));
+
+    let deep = polymer.after::<BigUint>(5000);
+    let diff = spread(&deep.elements);
+    println!("diff after 5000 has {} digits", diff.to_string().len());
+}
+
+#[test]
+fn sample_test() {
+    let patterns = [
+        ("CH", "B"),
+        ("HH", "N"),
+        ("CB", "H"),
+        ("NH", "C"),
+        ("HB", "C"),
+        ("HC", "B"),
+        ("HN", "C"),
+        ("NN", "C"),
+        ("BH", "H"),
+        ("NC", "B"),
+        ("NB", "B"),
+        ("BN", "B"),
+        ("BB", "N"),
+        ("BC", "B"),
+        ("CC", "N"),
+        ("CN", "C"),
+    ];
+    assert!(check_rules("NNCB", &patterns).is_clean());
+    let polymer = Polymer::new("NNCB", &patterns);
+    assert_eq!(polymer.cross_check(2).unwrap(), "NBCCNBBBCBHCB");
+    for depth in 0..=10 {
+        if let Err(e) = polymer.cross_check(depth) {
+            panic!("{e}");
         }
     }
-    println!("min: {:?} max {:?} diff {}", min, max, max.1 - min.1);
+    let history = polymer.element_history::<u128>(40);
+    assert_eq!(spread(&history[10]), 1588);
+    assert_eq!(spread(&history[40]), 2188189693529);
 }
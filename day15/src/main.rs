@@ -1,7 +1,11 @@
 use std::{
-    collections::{BinaryHeap, HashMap, HashSet},
+    collections::{BinaryHeap, HashMap},
+    env,
+    fmt::Write as _,
     fs,
+    time::Instant,
 };
+use termion::{color, style};
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
 struct Point {
@@ -34,7 +38,7 @@ impl PartialEq for PointQueueEntry {
 }
 impl PartialOrd for PointQueueEntry {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        other.cost_sum.partial_cmp(&self.cost_sum)
+        Some(self.cmp(other))
     }
 }
 
@@ -43,27 +47,87 @@ trait Grid<T> {
     fn getpos(&self, pos: Point) -> Option<T>;
 }
 
-impl Grid<u32> for Vec<Vec<u32>> {
+/// The scanned tile repeated `repeat_x` times to the right and `repeat_y`
+/// times down. Every tile step right or down adds `increment` to the risk,
+/// which wraps around from `max_risk` back to 1.
+struct CaveMap {
+    tile: Vec<Vec<u32>>,
+    repeat_x: usize,
+    repeat_y: usize,
+    increment: u32,
+    max_risk: u32,
+}
+
+impl CaveMap {
+    fn new(tile: Vec<Vec<u32>>, repeat_x: usize, repeat_y: usize) -> Self {
+        CaveMap {
+            tile,
+            repeat_x,
+            repeat_y,
+            increment: 1,
+            max_risk: 9,
+        }
+    }
+
+    /// Replaces the default wrap-around rule of adding 1 per tile and
+    /// wrapping from 9 back to 1.
+    fn wrapping(self, increment: u32, max_risk: u32) -> Self {
+        assert!(
+            (1..=u8::MAX as u32).contains(&max_risk),
+            "max_risk must be between 1 and 255"
+        );
+        CaveMap {
+            increment,
+            max_risk,
+            ..self
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.tile[0].len() * self.repeat_x
+    }
+
+    fn height(&self) -> usize {
+        self.tile.len() * self.repeat_y
+    }
+
+    /// Risk of every cell, row by row.
+    fn risks(&self) -> Vec<u8> {
+        let mut risks = Vec::with_capacity(self.width() * self.height());
+        for y in 0..self.height() as i32 {
+            for x in 0..self.width() as i32 {
+                risks.push(self.getpos(Point { x, y }).unwrap() as u8);
+            }
+        }
+        risks
+    }
+
+    fn destination(&self) -> Point {
+        Point {
+            x: self.width() as i32 - 1,
+            y: self.height() as i32 - 1,
+        }
+    }
+}
+
+impl Grid<u32> for CaveMap {
     fn getpos(&self, pos: Point) -> Option<u32> {
-        let repeat_x = 5;
-        let repeat_y = 5;
-        let height = self.len() * repeat_y;
-        let width = self[0].len() * repeat_x;
-        if pos.x < 0 || pos.y < 0 || pos.x as usize >= width || pos.y as usize >= height {
+        let (tile_width, tile_height) = (self.tile[0].len(), self.tile.len());
+        if pos.x < 0
+            || pos.y < 0
+            || pos.x as usize >= self.width()
+            || pos.y as usize >= self.height()
+        {
             None
         } else {
-            let tile_x: u32 = pos.x as u32 / self[0].len() as u32;
-            let tile_y: u32 = pos.y as u32 / self.len() as u32;
-            let cost = (self[pos.y as usize % self.len()][pos.x as usize % self[0].len()]
-                + tile_x
-                + tile_y
-                - 1)
-                % 9
-                + 1;
-            Some(cost)
+            let tile_x = pos.x as u32 / tile_width as u32;
+            let tile_y = pos.y as u32 / tile_height as u32;
+            let risk = self.tile[pos.y as usize % tile_height][pos.x as usize % tile_width];
+            Some((risk + (tile_x + tile_y) * self.increment - 1) % self.max_risk + 1)
         }
     }
 }
+
 fn test_move(
     input: &dyn Grid<u32>,
     known_costs: &mut HashMap<Point, PointCostEntry>,
@@ -87,20 +151,9 @@ fn test_move(
     }
 }
 
-fn main() {
-    let contents = fs::read_to_string("day15/input").expect("could not read input");
-    let input: Vec<Vec<u32>> = contents
-        .lines()
-        .map(|l| l.chars().map(|c| c.to_digit(10).unwrap()).collect())
-        .collect();
-
-    let start = Point { x: 0, y: 0 };
-
-    let dest = Point {
-        y: input.len() as i32 * 5 - 1,
-        x: input[0].len() as i32 * 5 - 1,
-    };
-
+/// Dijkstra from `start` to `dest`, returning the total risk of the entered
+/// cells and the path from `start` to `dest`, both included.
+fn lowest_risk(input: &dyn Grid<u32>, start: Point, dest: Point) -> Option<(u32, Vec<Point>)> {
     let mut known_costs = HashMap::new();
     let mut work_queue = BinaryHeap::new();
     work_queue.push(PointQueueEntry {
@@ -114,76 +167,317 @@ fn main() {
             via: start,
         },
     );
-    loop {
-        if let Some(point) = work_queue.pop() {
-            let pos = point.pos;
-            if pos == dest {
-                println!("reached with cost {:?}", point);
-                break;
+    while let Some(point) = work_queue.pop() {
+        let pos = point.pos;
+        if pos == dest {
+            let mut path = vec![dest];
+            while *path.last().unwrap() != start {
+                path.push(known_costs[path.last().unwrap()].via);
             }
-            let left = Point {
-                x: pos.x - 1,
-                y: pos.y,
-            };
-            let right = Point {
-                x: pos.x + 1,
-                y: pos.y,
-            };
-            let up = Point {
-                x: pos.x,
-                y: pos.y - 1,
+            path.reverse();
+            return Some((point.cost_sum, path));
+        }
+        if known_costs[&pos].cost_sum < point.cost_sum {
+            continue;
+        }
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let to = Point {
+                x: pos.x + dx,
+                y: pos.y + dy,
             };
-            let down = Point {
-                x: pos.x,
-                y: pos.y + 1,
+            test_move(input, &mut known_costs, &point, to, &mut work_queue);
+        }
+    }
+    None
+}
+
+/// Result of a bucket-queue search: distances and predecessors are indexed
+/// by `y * width + x`, with `NONE` for cells never reached.
+struct Route {
+    width: usize,
+    risk: Option<u32>,
+    dist: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+const NONE: u32 = u32::MAX;
+
+impl Route {
+    /// Number of cells the search assigned a distance to.
+    fn reached(&self) -> usize {
+        self.dist.iter().filter(|&&d| d != NONE).count()
+    }
+
+    fn point(&self, i: usize) -> Point {
+        Point {
+            x: (i % self.width) as i32,
+            y: (i / self.width) as i32,
+        }
+    }
+
+    /// Total risk of reaching `p`, if the search got there.
+    fn cumulative_risk(&self, p: Point) -> Option<u32> {
+        let d = self.dist[p.y as usize * self.width + p.x as usize];
+        (d != NONE).then_some(d)
+    }
+
+    /// The lowest-risk path from the start to `dest`, both included.
+    fn path(&self, dest: Point) -> Option<Vec<Point>> {
+        self.cumulative_risk(dest)?;
+        let mut i = dest.y as usize * self.width + dest.x as usize;
+        let mut path = vec![dest];
+        while self.prev[i] != NONE {
+            i = self.prev[i] as usize;
+            path.push(self.point(i));
+        }
+        path.reverse();
+        Some(path)
+    }
+}
+
+/// Colour for a cumulative risk on a blue (low) to yellow (high) scale.
+fn heat(risk: Option<u32>, max: u32) -> (u8, u8, u8) {
+    match risk {
+        None => (0, 0, 0),
+        Some(risk) => {
+            let t = risk as f64 / max.max(1) as f64;
+            (
+                (255.0 * t) as u8,
+                (200.0 * t) as u8,
+                (255.0 * (1.0 - t)) as u8,
+            )
+        }
+    }
+}
+
+/// Prints every cell's risk, coloured by the cumulative risk of reaching it,
+/// with the path in bold on a red background.
+fn render_ansi(map: &CaveMap, route: &Route, path: &[Point]) -> String {
+    let max = route.risk.unwrap_or(0);
+    let mut out = String::new();
+    for y in 0..map.height() as i32 {
+        for x in 0..map.width() as i32 {
+            let p = Point { x, y };
+            let (r, g, b) = heat(route.cumulative_risk(p), max);
+            write!(out, "{}", color::Fg(color::Rgb(r, g, b))).unwrap();
+            if path.contains(&p) {
+                write!(out, "{}{}", style::Bold, color::Bg(color::Red)).unwrap();
+            }
+            write!(out, "{}{}", map.getpos(p).unwrap(), style::Reset).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Binary PPM heat map of the cumulative risk with `scale` pixels per cell
+/// and the path drawn in white.
+fn render_ppm(map: &CaveMap, route: &Route, path: &[Point], scale: usize) -> Vec<u8> {
+    let max = route.risk.unwrap_or(0);
+    let (width, height) = (map.width(), map.height());
+    let mut on_path = vec![false; width * height];
+    for p in path {
+        on_path[p.y as usize * width + p.x as usize] = true;
+    }
+    let mut ppm = format!("P6\n{} {}\n255\n", width * scale, height * scale).into_bytes();
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            let (x, y) = (x / scale, y / scale);
+            let (r, g, b) = if on_path[y * width + x] {
+                (255, 255, 255)
+            } else {
+                heat(route.cumulative_risk(route.point(y * width + x)), max)
             };
-            test_move(&input, &mut known_costs, &point, left, &mut work_queue);
-            test_move(&input, &mut known_costs, &point, right, &mut work_queue);
-            test_move(&input, &mut known_costs, &point, up, &mut work_queue);
-            test_move(&input, &mut known_costs, &point, down, &mut work_queue);
+            ppm.extend([r, g, b]);
+        }
+    }
+    ppm
+}
+
+/// Dijkstra with a circular bucket queue (Dial's algorithm). Entering a cell
+/// costs between 1 and `max_risk`, so keys of queued cells never span more
+/// than `max_risk + 1` buckets. With `a_star`, keys include the Manhattan
+/// distance to `dest`, which changes by at most 1 per step.
+fn dial(map: &CaveMap, start: Point, dest: Point, a_star: bool) -> Route {
+    let (width, height) = (map.width(), map.height());
+    let risks = map.risks();
+    let index = |p: Point| p.y as usize * width + p.x as usize;
+    let heuristic = |i: usize| {
+        if a_star {
+            ((dest.x - (i % width) as i32).abs() + (dest.y - (i / width) as i32).abs()) as u32
         } else {
-            println!("empty");
-            break;
-        }
-    }
-/*
-    let mut path_set = HashSet::new();
-    println!("end");
-    let mut node = known_costs.get(&dest).unwrap();
-        println!("{:?}", node);
-    while node.via != start {
-        node = known_costs.get(&node.via).unwrap();
-        println!("{:?}", node);
-        path_set.insert(node.via);
-    }
-    for y in 0..input.len() * 5 {
-        for x in 0..input[0].len() * 5 {
-            if path_set.contains(&Point {
-                x: x as i32,
-                y: y as i32,
-            }) {
-                print!(
-                    "[{}]",
-                    input
-                        .getpos(Point {
-                            x: x as i32,
-                            y: y as i32
-                        })
-                        .unwrap_or(0)
-                )
-            } else {
-                print!(
-                    " {} ",
-                    input
-                        .getpos(Point {
-                            x: x as i32,
-                            y: y as i32
-                        })
-                        .unwrap_or(0)
-                )
+            0
+        }
+    };
+    let mut dist = vec![NONE; width * height];
+    let mut prev = vec![NONE; width * height];
+    let bucket_count = map.max_risk as usize + 2;
+    let mut buckets: Vec<Vec<u32>> = vec![Vec::new(); bucket_count];
+    let (start, dest) = (index(start), index(dest));
+
+    dist[start] = 0;
+    let mut key = heuristic(start);
+    buckets[key as usize % bucket_count].push(start as u32);
+    let mut queued = 1usize;
+    while queued > 0 {
+        let slot = key as usize % bucket_count;
+        let Some(i) = buckets[slot].pop() else {
+            key += 1;
+            continue;
+        };
+        queued -= 1;
+        let i = i as usize;
+        if dist[i] + heuristic(i) != key {
+            continue;
+        }
+        if i == dest {
+            return Route {
+                width,
+                risk: Some(dist[i]),
+                dist,
+                prev,
+            };
+        }
+        let (x, y) = (i % width, i / width);
+        let neighbours = [
+            (x > 0).then(|| i - 1),
+            (x + 1 < width).then(|| i + 1),
+            (y > 0).then(|| i - width),
+            (y + 1 < height).then(|| i + width),
+        ];
+        for n in neighbours.into_iter().flatten() {
+            let d = dist[i] + risks[n] as u32;
+            if d < dist[n] {
+                dist[n] = d;
+                prev[n] = i as u32;
+                buckets[(d + heuristic(n)) as usize % bucket_count].push(n as u32);
+                queued += 1;
             }
         }
-        println!("");
     }
-    */
+    Route {
+        width,
+        risk: None,
+        dist,
+        prev,
+    }
+}
+
+fn main() {
+    let contents = fs::read_to_string("day15/input").expect("could not read input");
+    let input: Vec<Vec<u32>> = contents
+        .lines()
+        .map(|l| l.chars().map(|c| c.to_digit(10).unwrap()).collect())
+        .collect();
+
+    let start = Point { x: 0, y: 0 };
+    for (repeat_x, repeat_y) in [(1, 1), (5, 5), (10, 10)] {
+        let map = CaveMap::new(input.clone(), repeat_x, repeat_y);
+        let dest = map.destination();
+        let expected = lowest_risk(&map, start, dest);
+        match &expected {
+            Some((risk, path)) => println!(
+                "{repeat_x}x{repeat_y}: lowest total risk {risk} in {} steps",
+                path.len() - 1
+            ),
+            None => println!("{repeat_x}x{repeat_y}: destination unreachable"),
+        }
+        for a_star in [false, true] {
+            let route = dial(&map, start, dest, a_star);
+            let path = route.path(dest).unwrap_or_default();
+            println!(
+                "  a_star {a_star}: {} steps, {} of {} cells reached",
+                path.len().saturating_sub(1),
+                route.reached(),
+                map.width() * map.height()
+            );
+        }
+    }
+
+    let map = CaveMap::new(input.clone(), 5, 5).wrapping(3, 12);
+    let route = dial(&map, start, map.destination(), true);
+    println!(
+        "5x5 adding 3 per tile up to 12: lowest total risk {:?}",
+        route.risk
+    );
+
+    let map = CaveMap::new(input.clone(), 1, 1);
+    let dest = map.destination();
+    let route = dial(&map, start, dest, false);
+    let path = route.path(dest).unwrap_or_default();
+    for p in &path {
+        println!(
+            "{},{} risk {} cumulative {}",
+            p.x,
+            p.y,
+            map.getpos(*p).unwrap(),
+            route.cumulative_risk(*p).unwrap()
+        );
+    }
+    print!("{}", render_ansi(&map, &route, &path));
+
+    if env::args().any(|a| a == "--export") {
+        let map = CaveMap::new(input.clone(), 5, 5);
+        let dest = map.destination();
+        let route = dial(&map, start, dest, false);
+        let path = route.path(dest).unwrap_or_default();
+        fs::write("day15/path.ppm", render_ppm(&map, &route, &path, 2))
+            .expect("could not write image");
+    }
+
+    if env::args().any(|a| a == "--large") {
+        let repeat = 5000 / input.len();
+        let map = CaveMap::new(input.clone(), repeat, repeat);
+        let timer = Instant::now();
+        let route = dial(&map, start, map.destination(), true);
+        println!(
+            "{}x{}: lowest total risk {:?} in {:?}",
+            map.width(),
+            map.height(),
+            route.risk,
+            timer.elapsed()
+        );
+    }
+}
+
+#[test]
+fn dial_test() {
+    let sample = [
+        "1163751742",
+        "1381373672",
+        "2136511328",
+        "3694931569",
+        "7463417111",
+        "1319128137",
+        "1359912421",
+        "3125421639",
+        "1293138521",
+        "2311944581",
+    ];
+    let tile: Vec<Vec<u32>> = sample
+        .iter()
+        .map(|l| l.chars().map(|c| c.to_digit(10).unwrap()).collect())
+        .collect();
+    let start = Point { x: 0, y: 0 };
+    let small = CaveMap::new(tile.clone(), 1, 1);
+    let large = CaveMap::new(tile.clone(), 5, 5);
+    let wrapped = CaveMap::new(tile, 3, 7).wrapping(3, 12);
+    for (map, expected) in [(&small, 40), (&large, 315)] {
+        let risk = lowest_risk(map, start, map.destination()).map(|(risk, _)| risk);
+        assert_eq!(risk, Some(expected));
+    }
+    // Both variants of Dial's algorithm agree with Dijkstra.
+    for map in [&small, &large, &wrapped] {
+        let dest = map.destination();
+        let dijkstra = lowest_risk(map, start, dest).map(|(risk, _)| risk);
+        for a_star in [false, true] {
+            let route = dial(map, start, dest, a_star);
+            assert_eq!(route.risk, dijkstra);
+            let path = route.path(dest).unwrap();
+            assert_eq!((path[0], *path.last().unwrap()), (start, dest));
+            let path_risk: u32 = path.iter().skip(1).map(|&p| map.getpos(p).unwrap()).sum();
+            assert_eq!(Some(path_risk), route.risk);
+            assert_eq!(route.cumulative_risk(dest), route.risk);
+        }
+    }
 }
@@ -1,3 +1,6 @@
+// num-derive 0.3 expands `FromPrimitive` into an impl inside a `const _` block.
+#![allow(non_local_definitions)]
+
 use std::fs;
 extern crate num;
 #[macro_use]
@@ -18,13 +21,73 @@ fn main() {
             let root = parse_header(&unhex(sample), &mut 0);
         }
     */
+    for sample in [
+        "38006F45291200",
+        "EE00D40C823060",
+        "9C0141080250320F1802104A08",
+    ] {
+        let root = parse_hex(sample).expect("could not parse sample");
+        println!("--- {sample}: {}", to_sexpr(root.as_ref()));
+        print!("{}", disassemble(root.as_ref()));
+    }
+
     let contents = fs::read_to_string("day16/input").expect("could not read input");
-    let root = parse_header(&unhex(&contents), &mut 0);
+    let root = parse_hex(&contents).expect("could not parse input");
     println!(
         "version sum: {} result {}",
         root.version_sum(),
-        root.evaluate()
+        root.evaluate().expect("could not evaluate input")
     );
+    println!("{}", to_sexpr(root.as_ref()));
+
+    let mut layout = FixedLayout {
+        length_type: LengthType::Packets,
+        extra_literal_groups: 0,
+    };
+    let reencoded = encode_hex(root.as_ref(), &mut layout).expect("could not encode");
+    match parse_hex(&reencoded) {
+        Ok(again) if same_tree(root.as_ref(), again.as_ref()) => println!(
+            "re-encoded with packet counts: {} hex digits",
+            reencoded.len()
+        ),
+        Ok(again) => println!(
+            "re-encoding does not match the input, it decodes to {}",
+            to_sexpr(again.as_ref())
+        ),
+        Err(e) => println!("re-encoding does not parse: {:?}", e),
+    }
+
+    let truncated = &contents.trim()[..contents.trim().len() / 2];
+    println!("first half of input: {:?}", parse_hex(truncated).err());
+
+    // A literal wider than 64 bits, and a product that no longer fits 128.
+    let wide = Literal {
+        value: u128::MAX / 3,
+        version: 1,
+        span: None,
+    };
+    let hex = encode_hex(&wide, &mut layout).expect("could not encode");
+    let wide = parse_hex(&hex).expect("could not parse wide literal");
+    println!("wide literal {hex}: {:?}", wide.evaluate());
+    let product = Operator {
+        op: ExpressionType::Product,
+        version: 0,
+        operands: vec![
+            wide,
+            Box::new(Literal {
+                value: 4,
+                version: 0,
+                span: None,
+            }),
+        ],
+        span: None,
+        length_type: None,
+    };
+    println!("{}: {:?}", to_sexpr(&product), product.evaluate());
+
+    let source = "max(3, 4*5) + (7 > 2)";
+    let hex = compile(source, &mut layout).expect("could not compile");
+    println!("{source}: {hex}");
 }
 
 fn unhex(data: &str) -> Vec<u8> {
@@ -45,12 +108,57 @@ fn unhex(data: &str) -> Vec<u8> {
         .0
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ParseError {
+    /// A field of `wanted` bits at `offset` runs past the end of the
+    /// transmission, which has only `available` bits left.
+    Truncated {
+        offset: usize,
+        wanted: usize,
+        available: usize,
+    },
+    /// A literal starting at `offset` does not fit into 128 bits.
+    LiteralTooLarge { offset: usize },
+    /// A sub-packet ran past the bit length given by its operator.
+    Overrun { offset: usize, end: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EvalError {
+    /// The result of a sum or product does not fit into 128 bits.
+    Overflow(ExpressionType),
+    /// Wrong number of operands, e.g. a comparison without exactly two.
+    Arity { op: ExpressionType, operands: usize },
+}
+
 trait Expression {
-    fn evaluate(&self) -> u64;
+    fn evaluate(&self) -> Result<u128, EvalError>;
     fn version_sum(&self) -> u64;
+    fn version(&self) -> u16;
+    fn kind(&self) -> ExpressionType;
+    /// Sub-packets of an operator, empty for literals.
+    fn operands(&self) -> &[Box<dyn Expression>];
+    /// Value of a literal, `None` for operators.
+    fn literal(&self) -> Option<u128>;
+    /// Position in the transmission, for decoded packets.
+    fn span(&self) -> Option<Span>;
+    /// How the sub-packets were delimited, for decoded operators.
+    fn length_type(&self) -> Option<LengthType>;
+}
+
+/// Structural equality: same versions, types, literal values and operands.
+fn same_tree(a: &dyn Expression, b: &dyn Expression) -> bool {
+    a.version() == b.version()
+        && a.kind() == b.kind()
+        && a.literal() == b.literal()
+        && a.operands().len() == b.operands().len()
+        && a.operands()
+            .iter()
+            .zip(b.operands())
+            .all(|(a, b)| same_tree(a.as_ref(), b.as_ref()))
 }
 
-#[derive(FromPrimitive, Debug)]
+#[derive(FromPrimitive, Debug, Clone, Copy, PartialEq, Eq)]
 enum ExpressionType {
     Sum = 0,
     Product = 1,
@@ -62,129 +170,777 @@ enum ExpressionType {
     Equal = 7,
 }
 
+/// Where a packet was found in a transmission, in bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    offset: usize,
+    bits: usize,
+}
+
 struct Literal {
-    value: u64,
+    value: u128,
     version: u16,
+    span: Option<Span>,
 }
 
 struct Operator {
     op: ExpressionType,
     version: u16,
     operands: Vec<Box<dyn Expression>>,
+    span: Option<Span>,
+    length_type: Option<LengthType>,
 }
 
 impl Literal {
-    pub fn parse(version: u16, data: &[u8], offset: &mut usize) -> Literal {
-        let mut value = 0u64;
+    pub fn parse(version: u16, reader: &mut BitReader) -> Result<Literal, ParseError> {
+        let start = reader.offset;
+        let mut value = 0u128;
         loop {
-            let val_part = get_bits(data, offset, 5);
-            value = value * 16 + (val_part & 0b01111) as u64;
+            let val_part = reader.read(5)?;
+            if value >> 124 != 0 {
+                return Err(ParseError::LiteralTooLarge { offset: start });
+            }
+            value = value << 4 | (val_part & 0b01111) as u128;
             if val_part & 0b10000 == 0 {
                 break;
             }
         }
-        Literal { value, version }
+        Ok(Literal {
+            value,
+            version,
+            span: None,
+        })
     }
 }
 
 impl Expression for Literal {
-    fn evaluate(&self) -> u64 {
-        self.value
+    fn evaluate(&self) -> Result<u128, EvalError> {
+        Ok(self.value)
     }
 
     fn version_sum(&self) -> u64 {
         self.version as u64
     }
+
+    fn version(&self) -> u16 {
+        self.version
+    }
+
+    fn kind(&self) -> ExpressionType {
+        ExpressionType::Literal
+    }
+
+    fn operands(&self) -> &[Box<dyn Expression>] {
+        &[]
+    }
+
+    fn literal(&self) -> Option<u128> {
+        Some(self.value)
+    }
+
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    fn length_type(&self) -> Option<LengthType> {
+        None
+    }
 }
 
 impl Operator {
-    pub fn parse(p_type: ExpressionType, version: u16, data: &[u8], offset: &mut usize) -> Self {
-        let len_type = get_bits(data, offset, 1);
+    pub fn parse(
+        p_type: ExpressionType,
+        version: u16,
+        reader: &mut BitReader,
+    ) -> Result<Self, ParseError> {
+        let len_type = reader.read(1)?;
         let mut operands = Vec::new();
         if len_type == 0 {
             // length in bits
-            let len_bits = get_bits(data, offset, 15) as usize;
-            let end_offset = *offset + len_bits;
-            while *offset < end_offset {
-                operands.push(parse_header(data, offset));
+            let len_bits = reader.read(15)? as usize;
+            let end_offset = reader.offset + len_bits;
+            while reader.offset < end_offset {
+                let start = reader.offset;
+                operands.push(parse_header(reader)?);
+                if reader.offset > end_offset {
+                    return Err(ParseError::Overrun {
+                        offset: start,
+                        end: end_offset,
+                    });
+                }
             }
         } else {
-            let len_packets = get_bits(data, offset, 11);
+            let len_packets = reader.read(11)?;
             for _p in 0..len_packets {
-                operands.push(parse_header(data, offset));
+                operands.push(parse_header(reader)?);
             }
         }
-        Operator {
+        Ok(Operator {
             op: p_type,
             version,
             operands,
-        }
+            span: None,
+            length_type: Some(if len_type == 0 {
+                LengthType::Bits
+            } else {
+                LengthType::Packets
+            }),
+        })
     }
 }
 impl Expression for Operator {
-    fn evaluate(&self) -> u64 {
-        let values = self.operands.iter().map(|e| e.evaluate());
-        match self.op {
-            ExpressionType::Sum => values.sum(),
-            ExpressionType::Product => values.product(),
-            ExpressionType::Minimum => values.fold(u64::MAX, |a, o| a.min(o)),
-            ExpressionType::Maximum => values.fold(0, |a, o| a.max(o)),
-            ExpressionType::Literal => panic!("unexpected literal"),
-            ExpressionType::GreaterThan => {
-                let values: Vec<_> = values.collect();
-                if values[0] > values[1] {
-                    1
-                } else {
-                    0
-                }
+    fn evaluate(&self) -> Result<u128, EvalError> {
+        let values = self
+            .operands
+            .iter()
+            .map(|e| e.evaluate())
+            .collect::<Result<Vec<_>, _>>()?;
+        let arity = EvalError::Arity {
+            op: self.op,
+            operands: values.len(),
+        };
+        let overflow = EvalError::Overflow(self.op);
+        match (self.op, values.as_slice()) {
+            (ExpressionType::Sum, _) => values
+                .iter()
+                .try_fold(0u128, |a, &o| a.checked_add(o))
+                .ok_or(overflow),
+            (ExpressionType::Product, _) => values
+                .iter()
+                .try_fold(1u128, |a, &o| a.checked_mul(o))
+                .ok_or(overflow),
+            (ExpressionType::Minimum, _) => values.iter().copied().min().ok_or(arity),
+            (ExpressionType::Maximum, _) => values.iter().copied().max().ok_or(arity),
+            (ExpressionType::GreaterThan, [a, b]) => Ok((a > b) as u128),
+            (ExpressionType::LessThan, [a, b]) => Ok((a < b) as u128),
+            (ExpressionType::Equal, [a, b]) => Ok((a == b) as u128),
+            _ => Err(arity),
+        }
+    }
+
+    fn version_sum(&self) -> u64 {
+        self.operands.iter().map(|o| o.version_sum()).sum::<u64>() + self.version as u64
+    }
+
+    fn version(&self) -> u16 {
+        self.version
+    }
+
+    fn kind(&self) -> ExpressionType {
+        self.op
+    }
+
+    fn operands(&self) -> &[Box<dyn Expression>] {
+        &self.operands
+    }
+
+    fn literal(&self) -> Option<u128> {
+        None
+    }
+
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    fn length_type(&self) -> Option<LengthType> {
+        self.length_type
+    }
+}
+/// Reads a transmission most significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, offset: 0 }
+    }
+
+    /// Reads the next `num_bits` (at most 64) bits as an unsigned number.
+    fn read(&mut self, num_bits: usize) -> Result<u64, ParseError> {
+        assert!(num_bits <= 64, "can read at most 64 bits at once");
+        let available = self.data.len() * 8 - self.offset;
+        if num_bits > available {
+            return Err(ParseError::Truncated {
+                offset: self.offset,
+                wanted: num_bits,
+                available,
+            });
+        }
+        let mut value = 0u64;
+        for _ in 0..num_bits {
+            let bit = self.data[self.offset / 8] >> (7 - self.offset % 8) & 1;
+            value = value << 1 | bit as u64;
+            self.offset += 1;
+        }
+        Ok(value)
+    }
+}
+
+fn parse_header(reader: &mut BitReader) -> Result<Box<dyn Expression>, ParseError> {
+    let start = reader.offset;
+    let version = reader.read(3)? as u16;
+    // Every 3-bit type id is a known expression type.
+    let p_type = num::FromPrimitive::from_u64(reader.read(3)?).unwrap();
+    let expression: Box<dyn Expression> = match p_type {
+        ExpressionType::Literal => {
+            let mut literal = Literal::parse(version, reader)?;
+            literal.span = Some(Span {
+                offset: start,
+                bits: reader.offset - start,
+            });
+            Box::new(literal)
+        }
+        et => {
+            let mut operator = Operator::parse(et, version, reader)?;
+            operator.span = Some(Span {
+                offset: start,
+                bits: reader.offset - start,
+            });
+            Box::new(operator)
+        }
+    };
+    Ok(expression)
+}
+
+fn parse_hex(hex: &str) -> Result<Box<dyn Expression>, ParseError> {
+    parse_header(&mut BitReader::new(&unhex(hex)))
+}
+
+impl ExpressionType {
+    fn symbol(&self) -> &'static str {
+        match self {
+            ExpressionType::Sum => "+",
+            ExpressionType::Product => "*",
+            ExpressionType::Minimum => "min",
+            ExpressionType::Maximum => "max",
+            ExpressionType::Literal => "lit",
+            ExpressionType::GreaterThan => ">",
+            ExpressionType::LessThan => "<",
+            ExpressionType::Equal => "=",
+        }
+    }
+}
+
+/// One line per packet, indented by nesting depth: bit offset and size,
+/// version, type id and either the literal value or the length type.
+fn disassemble(expr: &dyn Expression) -> String {
+    let mut out = String::new();
+    disassemble_into(expr, 0, &mut out);
+    out
+}
+
+fn disassemble_into(expr: &dyn Expression, depth: usize, out: &mut String) {
+    let position = match expr.span() {
+        Some(span) => format!("@{:<5} {:>5}b", span.offset, span.bits),
+        None => format!("{:13}", "-"),
+    };
+    let detail = match (expr.literal(), expr.length_type()) {
+        (Some(value), _) => format!("literal {value}"),
+        (None, Some(LengthType::Bits)) => {
+            let bits: usize = expr
+                .operands()
+                .iter()
+                .filter_map(|o| o.span())
+                .map(|s| s.bits)
+                .sum();
+            format!("{} length type 0 ({bits} bits)", expr.kind().symbol())
+        }
+        (None, Some(LengthType::Packets)) => format!(
+            "{} length type 1 ({} packets)",
+            expr.kind().symbol(),
+            expr.operands().len()
+        ),
+        (None, None) => expr.kind().symbol().to_string(),
+    };
+    out.push_str(&format!(
+        "{position} {:indent$}v{} type {} {detail}\n",
+        "",
+        expr.version(),
+        expr.kind() as u8,
+        indent = depth * 2
+    ));
+    for operand in expr.operands() {
+        disassemble_into(operand.as_ref(), depth + 1, out);
+    }
+}
+
+/// The expression as an S-expression such as `(+ (* 2 3) (min 5 7))`.
+fn to_sexpr(expr: &dyn Expression) -> String {
+    match expr.literal() {
+        Some(value) => value.to_string(),
+        None => {
+            let operands: Vec<String> = expr
+                .operands()
+                .iter()
+                .map(|o| to_sexpr(o.as_ref()))
+                .collect();
+            format!("({} {})", expr.kind().symbol(), operands.join(" "))
+        }
+    }
+}
+
+/// Bits written most significant first, as in a BITS transmission.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, num_bits: usize) {
+        for bit in (0..num_bits).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
             }
-            ExpressionType::LessThan => {
-                let values: Vec<_> = values.collect();
-                if values[0] < values[1] {
-                    1
-                } else {
-                    0
+            if value >> bit & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+
+    fn append(&mut self, other: &BitWriter) {
+        for i in 0..other.len {
+            self.write((other.bytes[i / 8] >> (7 - i % 8) & 1) as u64, 1);
+        }
+    }
+
+    /// The transmission as hex, padded with zero bits to whole bytes.
+    fn to_hex(&self) -> String {
+        self.bytes.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LengthType {
+    /// Length type id 0: total bit length of the sub-packets in 15 bits.
+    Bits = 0,
+    /// Length type id 1: number of sub-packets in 11 bits.
+    Packets = 1,
+}
+
+/// Choices the BITS format leaves to the encoder.
+trait Layout {
+    fn length_type(&mut self) -> LengthType;
+    /// Number of 4-bit groups for a literal that needs at least `minimal`.
+    fn literal_groups(&mut self, minimal: usize) -> usize;
+}
+
+struct FixedLayout {
+    length_type: LengthType,
+    extra_literal_groups: usize,
+}
+
+impl Layout for FixedLayout {
+    fn length_type(&mut self) -> LengthType {
+        self.length_type
+    }
+
+    fn literal_groups(&mut self, minimal: usize) -> usize {
+        minimal + self.extra_literal_groups
+    }
+}
+
+/// Small xorshift generator for random layouts and trees.
+#[cfg(test)]
+struct XorShift(u64);
+
+#[cfg(test)]
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Picks length types at random and pads literals with up to two leading
+/// zero groups.
+#[cfg(test)]
+struct RandomLayout(XorShift);
+
+#[cfg(test)]
+impl Layout for RandomLayout {
+    fn length_type(&mut self) -> LengthType {
+        if self.0.below(2) == 0 {
+            LengthType::Bits
+        } else {
+            LengthType::Packets
+        }
+    }
+
+    fn literal_groups(&mut self, minimal: usize) -> usize {
+        minimal + self.0.below(3) as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EncodeError {
+    /// Sub-packets longer than a 15-bit length field can describe.
+    TooManyBits(usize),
+    /// More sub-packets than an 11-bit count field can describe.
+    TooManyPackets(usize),
+}
+
+fn encode(
+    expr: &dyn Expression,
+    out: &mut BitWriter,
+    layout: &mut dyn Layout,
+) -> Result<(), EncodeError> {
+    out.write(expr.version() as u64, 3);
+    out.write(expr.kind() as u64, 3);
+    if let Some(value) = expr.literal() {
+        let minimal = ((128 - value.leading_zeros() as usize).div_ceil(4)).max(1);
+        let groups = layout.literal_groups(minimal);
+        for group in (0..groups).rev() {
+            let more = if group > 0 { 0b10000 } else { 0 };
+            let nibble = if group < 32 {
+                (value >> (group * 4) & 0xF) as u64
+            } else {
+                0
+            };
+            out.write(more | nibble, 5);
+        }
+        return Ok(());
+    }
+    let mut body = BitWriter::default();
+    for operand in expr.operands() {
+        encode(operand.as_ref(), &mut body, layout)?;
+    }
+    match layout.length_type() {
+        LengthType::Bits if body.len >= 1 << 15 => return Err(EncodeError::TooManyBits(body.len)),
+        LengthType::Bits => {
+            out.write(LengthType::Bits as u64, 1);
+            out.write(body.len as u64, 15);
+        }
+        LengthType::Packets if expr.operands().len() >= 1 << 11 => {
+            return Err(EncodeError::TooManyPackets(expr.operands().len()))
+        }
+        LengthType::Packets => {
+            out.write(LengthType::Packets as u64, 1);
+            out.write(expr.operands().len() as u64, 11);
+        }
+    }
+    out.append(&body);
+    Ok(())
+}
+
+fn encode_hex(expr: &dyn Expression, layout: &mut dyn Layout) -> Result<String, EncodeError> {
+    let mut out = BitWriter::default();
+    encode(expr, &mut out, layout)?;
+    Ok(out.to_hex())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SyntaxError {
+    /// Columns are 1-based character positions.
+    Unexpected {
+        column: usize,
+        found: char,
+    },
+    UnexpectedEnd {
+        expected: &'static str,
+    },
+    /// A number that does not fit into a 128-bit literal.
+    NumberTooLarge {
+        column: usize,
+    },
+}
+
+/// Recursive descent parser for infix expressions such as
+/// `max(3, 4*5) + (7 > 2)`. From loosest to tightest binding:
+/// comparisons `>`, `<`, `==` (exactly two operands, not chained), `+`,
+/// `*`, then numbers, parentheses and `min(...)`/`max(...)`.
+/// Every packet gets version 0.
+struct InfixParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl InfixParser {
+    fn parse(source: &str) -> Result<Box<dyn Expression>, SyntaxError> {
+        let mut parser = InfixParser {
+            chars: source.chars().collect(),
+            pos: 0,
+        };
+        let expr = parser.comparison()?;
+        match parser.peek() {
+            Some(found) => Err(parser.unexpected(found)),
+            None => Ok(expr),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn unexpected(&self, found: char) -> SyntaxError {
+        SyntaxError::Unexpected {
+            column: self.pos + 1,
+            found,
+        }
+    }
+
+    fn expect(&mut self, expected: char, what: &'static str) -> Result<(), SyntaxError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(found) => Err(self.unexpected(found)),
+            None => Err(SyntaxError::UnexpectedEnd { expected: what }),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Box<dyn Expression>, SyntaxError> {
+        let left = self.sum()?;
+        let op = match self.peek() {
+            Some('>') => ExpressionType::GreaterThan,
+            Some('<') => ExpressionType::LessThan,
+            Some('=') if self.chars.get(self.pos + 1) == Some(&'=') => {
+                self.pos += 1;
+                ExpressionType::Equal
+            }
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.sum()?;
+        Ok(operator(op, vec![left, right]))
+    }
+
+    fn sum(&mut self) -> Result<Box<dyn Expression>, SyntaxError> {
+        self.chain('+', ExpressionType::Sum, Self::product)
+    }
+
+    fn product(&mut self) -> Result<Box<dyn Expression>, SyntaxError> {
+        self.chain('*', ExpressionType::Product, Self::atom)
+    }
+
+    /// `a op b op c` becomes a single packet with three operands.
+    fn chain(
+        &mut self,
+        symbol: char,
+        op: ExpressionType,
+        operand: fn(&mut Self) -> Result<Box<dyn Expression>, SyntaxError>,
+    ) -> Result<Box<dyn Expression>, SyntaxError> {
+        let mut operands = vec![operand(self)?];
+        while self.peek() == Some(symbol) {
+            self.pos += 1;
+            operands.push(operand(self)?);
+        }
+        if operands.len() == 1 {
+            Ok(operands.pop().unwrap())
+        } else {
+            Ok(operator(op, operands))
+        }
+    }
+
+    fn atom(&mut self) -> Result<Box<dyn Expression>, SyntaxError> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let expr = self.comparison()?;
+                self.expect(')', "')'")?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
                 }
+                let digits: String = self.chars[start..self.pos].iter().collect();
+                let value = digits
+                    .parse()
+                    .map_err(|_| SyntaxError::NumberTooLarge { column: start + 1 })?;
+                Ok(Box::new(Literal {
+                    value,
+                    version: 0,
+                    span: None,
+                }))
             }
-            ExpressionType::Equal => {
-                let values: Vec<_> = values.collect();
-                if values[0] == values[1] {
-                    1
-                } else {
-                    0
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.pos;
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_ascii_alphabetic())
+                {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                let op = match name.as_str() {
+                    "min" => ExpressionType::Minimum,
+                    "max" => ExpressionType::Maximum,
+                    _ => {
+                        self.pos = start;
+                        return Err(self.unexpected(c));
+                    }
+                };
+                self.expect('(', "'('")?;
+                let mut operands = vec![self.comparison()?];
+                while self.peek() == Some(',') {
+                    self.pos += 1;
+                    operands.push(self.comparison()?);
                 }
+                self.expect(')', "')'")?;
+                Ok(operator(op, operands))
             }
+            Some(found) => Err(self.unexpected(found)),
+            None => Err(SyntaxError::UnexpectedEnd {
+                expected: "operand",
+            }),
         }
     }
+}
 
-    fn version_sum(&self) -> u64 {
-        self.operands.iter().map(|o| o.version_sum()).sum::<u64>() + self.version as u64
+fn operator(op: ExpressionType, operands: Vec<Box<dyn Expression>>) -> Box<dyn Expression> {
+    Box::new(Operator {
+        op,
+        version: 0,
+        operands,
+        span: None,
+        length_type: None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CompileError {
+    Syntax(SyntaxError),
+    Encode(EncodeError),
+}
+
+/// Compiles an infix expression into a hex BITS transmission.
+fn compile(source: &str, layout: &mut dyn Layout) -> Result<String, CompileError> {
+    let expr = InfixParser::parse(source).map_err(CompileError::Syntax)?;
+    encode_hex(expr.as_ref(), layout).map_err(CompileError::Encode)
+}
+
+/// A random expression tree at most `depth` levels deep. Comparisons get
+/// exactly two operands, the other operators one to four.
+#[cfg(test)]
+fn random_tree(rng: &mut XorShift, depth: usize) -> Box<dyn Expression> {
+    let version = rng.below(8) as u16;
+    if depth == 0 || rng.below(3) == 0 {
+        let value = ((rng.next() as u128) << 64 | rng.next() as u128) >> rng.below(128);
+        return Box::new(Literal {
+            value,
+            version,
+            span: None,
+        });
+    }
+    let op: ExpressionType = num::FromPrimitive::from_u64(rng.below(7)).unwrap();
+    let op = if op == ExpressionType::Literal {
+        ExpressionType::Equal
+    } else {
+        op
+    };
+    let count = match op {
+        ExpressionType::GreaterThan | ExpressionType::LessThan | ExpressionType::Equal => 2,
+        _ => 1 + rng.below(4) as usize,
+    };
+    Box::new(Operator {
+        op,
+        version,
+        operands: (0..count).map(|_| random_tree(rng, depth - 1)).collect(),
+        span: None,
+        length_type: None,
+    })
+}
+
+/// Encodes random trees with random layouts and checks that decoding gives
+/// back the same tree.
+#[test]
+fn round_trip_test() {
+    let mut rng = XorShift(0x2021_1216);
+    for _ in 0..1000 {
+        let tree = random_tree(&mut rng, 5);
+        let hex = encode_hex(tree.as_ref(), &mut RandomLayout(XorShift(rng.next() | 1))).unwrap();
+        let decoded = parse_hex(&hex).unwrap();
+        assert!(
+            same_tree(tree.as_ref(), decoded.as_ref()),
+            "round trip failed for {hex}"
+        );
     }
 }
-fn get_bits(data: &[u8], offset: &mut usize, num_bits: usize) -> u16 {
-    let start = *offset / 8;
-    let end = (*offset + num_bits + 7) / 8;
-    let win = &data[start..end];
-    let data = match end - start {
-        1 => u32::from_be_bytes([win[0], 0, 0, 0]),
-        2 => u32::from_be_bytes([win[0], win[1], 0, 0]),
-        3 => u32::from_be_bytes([win[0], win[1], win[2], 0]),
-        4 => u32::from_be_bytes([win[0], win[1], win[2], win[3]]),
-        _ => panic!("too many bits for u16"),
+
+#[test]
+fn compile_test() {
+    let mut layout = FixedLayout {
+        length_type: LengthType::Bits,
+        extra_literal_groups: 0,
     };
-    let rem_bits = 32 - num_bits;
-    let shifted = data << (*offset % 8);
-    let mask = !((0b1 << rem_bits) - 1);
-    *offset += num_bits;
-    ((shifted & mask) >> rem_bits) as u16
+    for (source, expected) in [
+        ("max(3, 4*5) + (7 > 2)", 21),
+        ("2 * 3 + min(5, 7)", 11),
+        ("(1 + 3) == 2 * 2", 1),
+        (
+            "min(4 < 9, 8, 2 * (1 + 1)) * 100000000000000000000000",
+            100000000000000000000000,
+        ),
+    ] {
+        let hex = compile(source, &mut layout).unwrap();
+        let value = parse_hex(&hex).unwrap().evaluate().unwrap();
+        assert_eq!(value, expected, "{source} compiled to {hex}");
+    }
 }
 
-fn parse_header(data: &[u8], offset: &mut usize) -> Box<dyn Expression> {
-    let version = get_bits(data, offset, 3);
-    let p_type = num::FromPrimitive::from_u16(get_bits(data, offset, 3));
-    match p_type {
-        Some(ExpressionType::Literal) => Box::new(Literal::parse(version, data, offset)),
-        Some(et) => Box::new(Operator::parse(et, version, data, offset)),
-        None => panic!("unknown operator {:?}", p_type),
+#[test]
+fn syntax_error_test() {
+    let unexpected = |column, found| Some(SyntaxError::Unexpected { column, found });
+    let end = |expected| Some(SyntaxError::UnexpectedEnd { expected });
+    for (source, error) in [
+        ("1 +", end("operand")),
+        ("max 3", unexpected(5, '3')),
+        ("1 = 1", unexpected(3, '=')),
+        ("2 > 1 > 0", unexpected(7, '>')),
+        ("(1 + 2", end("')'")),
+        ("1 - 2", unexpected(3, '-')),
+    ] {
+        assert_eq!(InfixParser::parse(source).err(), error, "{source}");
     }
 }
+
+#[test]
+fn printing_test() {
+    let root = parse_hex("38006F45291200").unwrap();
+    assert_eq!(to_sexpr(root.as_ref()), "(< 10 20)");
+    assert_eq!(
+        disassemble(root.as_ref()),
+        "@0        49b v1 type 6 < length type 0 (27 bits)\n\
+         @22       11b   v6 type 4 literal 10\n\
+         @33       16b   v2 type 4 literal 20\n"
+    );
+
+    let root = parse_hex("EE00D40C823060").unwrap();
+    assert_eq!(to_sexpr(root.as_ref()), "(max 1 2 3)");
+    assert!(disassemble(root.as_ref())
+        .starts_with("@0        51b v7 type 3 max length type 1 (3 packets)\n"));
+
+    let mut layout = FixedLayout {
+        length_type: LengthType::Bits,
+        extra_literal_groups: 0,
+    };
+    let hex = compile("2 * 3 + min(5, 7)", &mut layout).unwrap();
+    let root = parse_hex(&hex).unwrap();
+    assert_eq!(to_sexpr(root.as_ref()), "(+ (* 2 3) (min 5 7))");
+    assert_eq!(
+        disassemble(root.as_ref()),
+        "@0       110b v0 type 0 + length type 0 (88 bits)\n\
+         @22       44b   v0 type 1 * length type 0 (22 bits)\n\
+         @44       11b     v0 type 4 literal 2\n\
+         @55       11b     v0 type 4 literal 3\n\
+         @66       44b   v0 type 2 min length type 0 (22 bits)\n\
+         @88       11b     v0 type 4 literal 5\n\
+         @99       11b     v0 type 4 literal 7\n"
+    );
+}
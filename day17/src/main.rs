@@ -1,60 +1,287 @@
 use regex::Regex;
-use std::{collections::BinaryHeap, fs};
+use std::fs;
 
 fn main() {
     let re = Regex::new(r"target area: x=(-?\d+)..(-?\d+), y=(-?\d+)..(-?\d+)").unwrap();
 
     let content = fs::read_to_string("day17/input").unwrap();
     let vals = re.captures_iter(&content).next().unwrap();
-    let vals = vec![&vals[1], &vals[2], &vals[3], &vals[4]]
+    let vals = [&vals[1], &vals[2], &vals[3], &vals[4]]
         .iter()
-        .map(|v| v.parse::<i32>().unwrap())
+        .map(|v| v.parse::<i64>().unwrap())
         .collect::<Vec<_>>();
-    let (x1, x2, y1, y2) = (vals[0], vals[1], vals[2], vals[3]);
-    println!("input: {x1} {x2}  {y1} {y2}");
-
-    let v0 = y1.abs() - 1;
-    let v_terminal = v0 + 1;
-    let max_height = (v0 * v_terminal) / 2;
-
-    println!("v0: {v0} max_height: {max_height}");
-
-    let v_x_min = ((2 * x1 - 1) as f64).sqrt() as i32;
-    let v_x_max = x2 + 1;
-    let v_y_min = -v_terminal - 1;
-    let v_y_max = v_terminal + 1;
-    let mut count = 0;
-    let mut total = 0;
-    /*
-        let mut x = 0;
-        let mut v = 0;
-        let mut sums = Vec::new();
-        while x < x2.max(y2.abs()) {
-            v += 1;
-            x += v;
-            sums.push((v, x));
+    let target = Target::new(vals[0], vals[1], vals[2], vals[3]);
+    println!("input: {:?}", target);
+
+    let shots = target
+        .solve()
+        .expect("infinitely many velocities hit the target");
+    let highest = shots.iter().max_by_key(|s| s.apex).unwrap();
+    println!(
+        "max_height: {} at v=({},{}) inside at steps {:?} count: {}",
+        highest.apex,
+        highest.v_x,
+        highest.v_y,
+        highest.steps,
+        shots.len()
+    );
+
+    // The same target mirrored into every quadrant, plus targets above the
+    // launcher and around it.
+    let Target { x1, x2, y1, y2 } = target;
+    let targets = [
+        Target::new(-x2, -x1, y1, y2),
+        Target::new(x1, x2, -y2, -y1),
+        Target::new(-x2, -x1, -y2, -y1),
+        Target::new(0, 0, 10, 20),
+        Target::new(-5, 5, -5, 5),
+    ];
+    for target in targets {
+        match target.solve() {
+            Ok(shots) => println!(
+                "{:?}: {} velocities, max_height {:?}",
+                target,
+                shots.len(),
+                shots.iter().map(|s| s.apex).max()
+            ),
+            Err(unbounded) => println!("{:?}: {:?}", target, unbounded),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Target {
+    x1: i64,
+    x2: i64,
+    y1: i64,
+    y2: i64,
+}
+
+/// Inclusive range of step numbers, `last` is `None` if it never ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Steps {
+    first: i64,
+    last: Option<i64>,
+}
+
+impl Steps {
+    fn intersect(&self, other: &Steps) -> Option<Steps> {
+        let first = self.first.max(other.first);
+        let last = match (self.last, other.last) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        match last {
+            Some(last) if last < first => None,
+            _ => Some(Steps { first, last }),
+        }
+    }
+}
+
+/// A launch velocity that hits the target.
+#[derive(Debug, Clone, Copy)]
+struct Shot {
+    v_x: i64,
+    v_y: i64,
+    /// Highest point of the trajectory, including the launcher at 0.
+    apex: i64,
+    /// First stretch of steps during which the probe is inside the target.
+    steps: Steps,
+}
+
+/// The target contains height 0 and the probe stops inside it horizontally
+/// for each of these `v_x`. Every upward shot comes back to height 0 at step
+/// `2 * v_y + 1`, so each of them hits with any `v_y >= 0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Unbounded {
+    v_x: Vec<i64>,
+}
+
+/// Distance covered after `n` steps by a velocity `v` that drops by one per
+/// step, ignoring drag stopping at zero.
+fn travelled(v: i64, n: i64) -> i64 {
+    v * n - n * (n - 1) / 2
+}
+
+/// Steps `n` in `from..=to` where `lo <= f(n) <= hi`, for `f` monotone over
+/// that range. An open range needs `f` to run past the bounds eventually.
+fn steps_within(
+    f: impl Fn(i64) -> i64,
+    from: i64,
+    to: Option<i64>,
+    lo: i64,
+    hi: i64,
+) -> Option<Steps> {
+    let rising = f(to.unwrap_or(from + 1)) >= f(from);
+    let inside = |n: i64| (lo..=hi).contains(&f(n));
+    // Past the target: beyond `hi` when rising, below `lo` when falling.
+    let past = |n: i64| if rising { f(n) > hi } else { f(n) < lo };
+    let before = |n: i64| !past(n) && !inside(n);
+    let to = match to {
+        Some(to) => to,
+        None => {
+            let mut to = from.max(1);
+            while !past(to) {
+                to *= 2;
+            }
+            to
+        }
+    };
+    // First step that is no longer before the target.
+    let (mut a, mut b) = (from, to + 1);
+    while a < b {
+        let m = (a + b) / 2;
+        if before(m) {
+            a = m + 1;
+        } else {
+            b = m;
+        }
+    }
+    let first = a;
+    if first > to || !inside(first) {
+        return None;
+    }
+    // Last step that is still inside.
+    let (mut a, mut b) = (first, to);
+    while a < b {
+        let m = (a + b + 1) / 2;
+        if inside(m) {
+            a = m;
+        } else {
+            b = m - 1;
         }
-        println!("{:?}", sums);
-        let max_steps = sums.len() - 1;
+    }
+    Some(Steps {
+        first,
+        last: Some(a),
+    })
+}
 
-        let mut x_hits = vec![Vec::new(); max_steps];
-        for vx_0 in v_x_min..v_x_max {
-            step_hits.push(1);
-            x_hits.push(step_hits);
+impl Target {
+    fn new(x1: i64, x2: i64, y1: i64, y2: i64) -> Self {
+        Target {
+            x1: x1.min(x2),
+            x2: x1.max(x2),
+            y1: y1.min(y2),
+            y2: y1.max(y2),
+        }
+    }
+
+    /// Steps at which a probe launched with `v_x` is inside `x1..=x2`. Drag
+    /// stops it after `|v_x|` steps, so if it stops inside it stays there.
+    fn x_steps(&self, v_x: i64) -> Option<Steps> {
+        let s = v_x.abs();
+        let (lo, hi) = if v_x < 0 {
+            (-self.x2, -self.x1)
+        } else {
+            (self.x1, self.x2)
+        };
+        if s == 0 {
+            return (lo <= 0 && hi >= 0).then_some(Steps {
+                first: 1,
+                last: None,
+            });
+        }
+        let moving = steps_within(|n| travelled(s, n), 1, Some(s), lo, hi)?;
+        if (lo..=hi).contains(&travelled(s, s)) {
+            Some(Steps {
+                first: moving.first,
+                last: None,
+            })
+        } else {
+            Some(moving)
+        }
+    }
+
+    /// Steps at which a probe launched with `v_y` is inside `y1..=y2`. The
+    /// height rises until step `v_y` and falls from then on, so a target
+    /// above the launcher can be crossed twice.
+    fn y_steps(&self, v_y: i64) -> Vec<Steps> {
+        let peak = v_y.max(0);
+        let f = |n| travelled(v_y, n);
+        let rising = if peak >= 1 {
+            steps_within(f, 1, Some(peak), self.y1, self.y2)
+        } else {
+            None
+        };
+        let falling = steps_within(f, peak + 1, None, self.y1, self.y2);
+        match (rising, falling) {
+            // Both passes touch at the peak: merge them.
+            (Some(r), Some(fl)) if r.last == Some(fl.first - 1) => vec![Steps {
+                first: r.first,
+                last: fl.last,
+            }],
+            (r, fl) => r.into_iter().chain(fl).collect(),
+        }
+    }
+
+    /// Every velocity that hits the target, ordered by `v_x` then `v_y`.
+    /// Each axis gives the steps at which it is in range, and a velocity hits
+    /// if the two sets overlap.
+    fn solve(&self) -> Result<Vec<Shot>, Unbounded> {
+        // The first step already moves by the full velocity, so faster shots
+        // overshoot. Upwards shots come back down through the same heights,
+        // and apart from height 0 those are all at least `v_y` away from it.
+        // Height 0 is reached again at step `2 * v_y + 1`, which only helps
+        // while x is still in range.
+        let (v_x_min, v_x_max) = (self.x1.min(0), self.x2.max(0));
+        let v_y_min = self.y1.min(0);
+        let straddles = self.y1 <= 0 && self.y2 >= 0;
+        let v_y_max = self.y1.abs().max(self.y2.abs());
+        if straddles && self.y2 >= 0 {
+            let stalled: Vec<i64> = (v_x_min..=v_x_max)
+                .filter(|&v_x| self.x_steps(v_x).is_some_and(|s| s.last.is_none()))
+                .collect();
+            if !stalled.is_empty() {
+                return Err(Unbounded { v_x: stalled });
+            }
         }
-    */
-    for v_x in v_x_min..v_x_max {
-        for v_y in v_y_min..v_y_max {
-            total += 1;
-            if simulate(v_x, v_y, x1, x2, y1, y2) {
-                count += 1;
+        let mut shots = Vec::new();
+        for v_x in v_x_min..=v_x_max {
+            let Some(x_steps) = self.x_steps(v_x) else {
+                continue;
+            };
+            let v_y_max = match x_steps.last {
+                Some(last) if straddles => v_y_max.max((last - 1) / 2),
+                _ => v_y_max,
+            };
+            for v_y in v_y_min..=v_y_max {
+                if let Some(steps) = self
+                    .y_steps(v_y)
+                    .iter()
+                    .find_map(|y_steps| y_steps.intersect(&x_steps))
+                {
+                    shots.push(Shot {
+                        v_x,
+                        v_y,
+                        apex: travelled(v_y, v_y.max(0)),
+                        steps,
+                    });
+                }
             }
         }
+        Ok(shots)
+    }
+
+    /// Reference solution: simulates every velocity with both components in
+    /// `-limit..=limit`.
+    #[cfg(test)]
+    fn brute_force(&self, limit: i64) -> Vec<(i64, i64)> {
+        let mut hits = Vec::new();
+        for v_x in -limit..=limit {
+            for v_y in -limit..=limit {
+                if simulate(v_x, v_y, self) {
+                    hits.push((v_x, v_y));
+                }
+            }
+        }
+        hits
     }
-    println!("count: {count} of {total} x({v_x_min}..{v_x_max}) y({v_y_min}..{v_y_max})");
 }
 
-fn simulate(v_x0: i32, v_y0: i32, x1: i32, x2: i32, y1: i32, y2: i32) -> bool {
+#[cfg(test)]
+fn simulate(v_x0: i64, v_y0: i64, target: &Target) -> bool {
+    let Target { x1, x2, y1, y2 } = *target;
     let (mut x, mut y) = (0, 0);
     let (mut vel_x, mut vel_y) = (v_x0, v_y0);
     loop {
@@ -65,8 +292,49 @@ fn simulate(v_x0: i32, v_y0: i32, x1: i32, x2: i32, y1: i32, y2: i32) -> bool {
         if x >= x1 && x <= x2 && y >= y1 && y <= y2 {
             return true;
         }
-        if x > x2 || y < y1 {
+        if (vel_x >= 0 && x > x2) || (vel_x <= 0 && x < x1) || (y < y1 && vel_y < 0) {
             return false;
         }
     }
 }
+
+#[test]
+fn sample_test() {
+    let shots = Target::new(20, 30, -10, -5).solve().unwrap();
+    assert_eq!(shots.iter().map(|s| s.apex).max(), Some(45));
+    assert_eq!(shots.len(), 112);
+}
+
+#[test]
+fn simulation_test() {
+    for target in [
+        Target::new(20, 30, -10, -5),
+        Target::new(-30, -20, -10, -5),
+        Target::new(20, 30, 5, 10),
+        Target::new(-30, -20, 5, 10),
+        Target::new(-3, 4, 6, 9),
+        Target::new(0, 0, 10, 20),
+        Target::new(-4, 6, -8, -1),
+        Target::new(20, 20, -1, 1),
+        Target::new(-54, -46, -2, 3),
+    ] {
+        let shots = target.solve().unwrap();
+        assert_eq!(
+            shots.iter().map(|s| (s.v_x, s.v_y)).collect::<Vec<_>>(),
+            target.brute_force(100),
+            "solver and simulation disagree for {:?}",
+            target
+        );
+    }
+}
+
+#[test]
+fn unbounded_test() {
+    let target = Target::new(-5, 5, -5, 5);
+    let unbounded = target.solve().unwrap_err();
+    assert_eq!(unbounded.v_x, vec![-2, -1, 0, 1, 2]);
+    for v_x in unbounded.v_x {
+        assert!(simulate(v_x, 100, &target));
+    }
+    assert!(!simulate(3, 100, &target));
+}